@@ -1,15 +1,21 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     error,
     fmt,
     io::{self, BufRead, Write},
+    ops::Range,
+    str::FromStr,
 };
 
-use super::vm::{
-    self,
-    Instruction,
-    SrcOperand,
-    DstOperand,
+use super::{
+    isa::{FlowClass, Operand},
+    map::{Comments, Entries, ProjectMap, Region, RegionKind, Signatures},
+    vm::{
+        self,
+        Instruction,
+        SrcOperand,
+        DstOperand,
+    },
 };
 
 #[derive(Debug)]
@@ -41,10 +47,53 @@ impl fmt::Display for DisAsmError {
 
 impl error::Error for DisAsmError { }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourcePos {
+    pub file: Option<String>,
+    pub line: usize,
+    pub col: usize,
+    pub expansion: Option<Box<Expansion>>,
+}
+
+// where a macro was invoked, for positions inside its expansion
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub site: SourcePos,
+}
+
+impl SourcePos {
+    pub fn expanded_at(&self, expansion: &Expansion) -> Self {
+        let expansion = match &self.expansion {
+            // a position already inside an expansion is in a nested macro
+            Some(inner) => Expansion {
+                name: inner.name.clone(),
+                site: inner.site.expanded_at(expansion),
+            },
+            None => expansion.clone(),
+        };
+        SourcePos { expansion: Some(Box::new(expansion)), ..self.clone() }
+    }
+}
+
+impl fmt::Display for SourcePos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.col)?;
+        if let Some(exp) = &self.expansion {
+            write!(f, ", in expansion of macro \"{}\" at {}",
+              exp.name, exp.site)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum AsmError {
     VmError(vm::Error),
-    SyntaxError(String),
+    SyntaxError(SourcePos, String),
     LabelFileSyntaxError(String),
     IOError(io::Error),
 }
@@ -66,8 +115,8 @@ impl fmt::Display for AsmError {
         match self {
             AsmError::VmError(e) => write!(f, "VM error: {}", e),
             AsmError::IOError(e) => write!(f, "I/O error: {}", e),
-            AsmError::SyntaxError(line) =>
-              write!(f, "syntax error: \"{}\"", line),
+            AsmError::SyntaxError(pos, msg) =>
+              write!(f, "syntax error at {}: {}", pos, msg),
             AsmError::LabelFileSyntaxError(line) =>
               write!(f, "label file syntax error: \"{}\"", line),
         }
@@ -76,102 +125,539 @@ impl fmt::Display for AsmError {
 
 impl error::Error for AsmError { }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum AsmItem {
     Instruction(Instruction),
     Value(u16),
+    String(StringKind, Vec<u16>),
+}
+
+impl AsmItem {
+    pub fn size(&self) -> usize {
+        match self {
+            AsmItem::Instruction(instr) => instr.size(),
+            AsmItem::Value(_) => 1,
+            AsmItem::String(StringKind::Prefixed, chars) => chars.len() + 1,
+            AsmItem::String(StringKind::Raw, chars) => chars.len(),
+            AsmItem::String(StringKind::Out, chars) => 2 * chars.len(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StringKind {
+    // a length word followed by that many character words
+    Prefixed,
+    // a bare run of character words
+    Raw,
+    // a run of `out` instructions with immediate operands
+    Out,
+}
+
+impl StringKind {
+    pub fn directive(&self) -> &'static str {
+        match self {
+            StringKind::Prefixed => ".str",
+            StringKind::Raw => ".ascii",
+            StringKind::Out => ".out",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StringHint {
+    Prefixed,
+    Raw(usize),
+    Never,
 }
 
 pub type Labels = HashMap<usize, String>;
+pub type StringHints = HashMap<usize, StringHint>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum XrefKind {
+    Jump,
+    Call,
+    Read,
+    Write,
+}
+
+impl fmt::Display for XrefKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XrefKind::Jump => write!(f, "jump"),
+            XrefKind::Call => write!(f, "call"),
+            XrefKind::Read => write!(f, "read"),
+            XrefKind::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Xref {
+    pub from: usize,
+    pub kind: XrefKind,
+}
+
+pub type Xrefs = HashMap<usize, Vec<Xref>>;
+
+// how an immediate word renders: by label, as a character or as a number
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Word<'a> {
+    Symbol(&'a str),
+    Char(char),
+    Number(u16),
+}
+
+// known register values, with the address of the word they came from
+type RegConsts = [Option<(u16, usize)>; 8];
 
 #[derive(Clone, Debug)]
 pub struct ImageMap {
     pub stmts: Vec<(usize, AsmItem)>,
     pub labels: Labels,
     pub origins: HashSet<usize>,
+    pub xrefs: Xrefs,
+    // room for the longest `label: `, for aligned output
+    label_width: usize,
 }
 
 impl ImageMap {
     pub fn new(memory: &[u16], opts: &DisAsmOpts) -> ImageMap {
         let mut stmts = Vec::new();
-        let mut labels = opts.initial_labels.as_ref()
-            .map(|m| m.clone())
-            .unwrap_or_else(|| HashMap::new());
+        let mut labels = opts.initial_labels.clone()
+            .unwrap_or_default();
         let mut origins = HashSet::new();
+        let mut xrefs = HashMap::new();
+        let mut consts = [None; 8];
         let mut next_label = 0;
         let mut ip = 0;
 
+        if opts.autolabel {
+            for &entry in opts.entries.iter().filter(|&&e| e < memory.len()) {
+                labels.entry(entry).or_insert_with(|| {
+                    next_label += 1;
+                    format!("entry{}", next_label - 1)
+                });
+            }
+        }
+
         while ip < memory.len() {
-            if let Ok((new_ip, instr)) = Instruction::decode(memory, ip) {
+            let region = opts.regions.iter().find(|r| r.contains(ip));
+            let kind = region.map(|r| &r.kind);
+            // an entry point is code, and nothing runs over the start of one
+            let entry = opts.entries.contains(&ip);
+            let room = opts.entries.range(ip + 1..).next()
+              .map_or(memory.len(), |&next| next) - ip;
+
+            if let (false, Some(RegionKind::Data | RegionKind::Struct(_))) =
+              (entry, kind) {
+                stmts.push((ip, AsmItem::Value(memory[ip])));
+                consts = [None; 8];
+                ip += 1;
+            } else if let Some(item) =
+              Self::detect_string(memory, ip, region, &labels, opts)
+                .filter(|item| !entry && item.size() <= room) {
+                let size = item.size();
+                stmts.push((ip, item));
+                consts = [None; 8];
+                ip += size;
+            } else if let Some((new_ip, instr)) =
+              Instruction::decode(memory, ip).ok()
+                .filter(|(new_ip, _)| new_ip - ip <= room) {
+                // anything could jump to a label with other register values
+                if labels.contains_key(&ip) {
+                    consts = [None; 8];
+                }
+
                 stmts.push((ip, AsmItem::Instruction(instr)));
+                let access = Self::data_access(ip, &instr, &consts);
+                Self::add_xrefs(ip, &instr, access, &mut xrefs);
 
                 if opts.autolabel {
-                    Self::add_labels(ip, &instr,
+                    Self::add_labels(ip, &instr, access,
                       &mut labels, &mut origins, &mut next_label);
                 }
 
+                Self::track_consts(ip, &instr, &mut consts);
                 ip = new_ip;
             } else {
                 stmts.push((ip, AsmItem::Value(memory[ip])));
+                consts = [None; 8];
                 ip += 1;
             }
         }
 
-        ImageMap { stmts, labels, origins }
+        if opts.fold_out {
+            stmts = Self::fold_out(stmts, &labels);
+        }
+
+        let label_width = labels.values()
+          .map(|l| l.len() + 2)
+          .max()
+          .unwrap_or(0);
+
+        ImageMap { stmts, labels, origins, xrefs, label_width }
     }
 
     pub fn disasm<W: Write>(&self, w: &mut W, opts: &DisAsmOpts
       ) -> Result<(), DisAsmError> {
+        if opts.round_trip {
+            self.write_label_constants(w, opts)?;
+        }
+
+        let mut highlighting = false;
         for (ip, stmt) in &self.stmts {
+            let highlight = opts.highlights.iter()
+              .any(|r| r.start < ip + stmt.size() && *ip < r.end);
+            if highlight && !highlighting {
+                writeln!(w, "; >>> modified at runtime")?;
+            } else if !highlight && highlighting {
+                writeln!(w, "; <<< end modified")?;
+            }
+            highlighting = highlight;
+
+            self.write_annotations(w, *ip, opts)?;
+            if opts.xrefs && self.labels.contains_key(ip) {
+                self.write_xrefs(w, *ip)?;
+            }
+
+            if let (true, AsmItem::String(StringKind::Out, chars)) =
+              (opts.line_addrs, stmt) {
+                self.write_expanded_out(w, *ip, chars, opts)?;
+                continue;
+            }
+
             if opts.line_addrs {
                 write!(w, "{}\t", ip)?;
             }
-            stmt.disasm(*ip, self, w)?;
+            stmt.disasm(*ip, self, opts, w)?;
+        }
+        if highlighting {
+            writeln!(w, "; <<< end modified")?;
         }
         Ok(())
     }
 
-    fn add_labels(ip: usize, instr: &Instruction,
-      labels: &mut Labels, origins: &mut HashSet<usize>,
-      next_label: &mut usize) {
-        match instr {
-            Instruction::Jmp(SrcOperand::Immediate(dst)) => {
-                labels.entry(*dst as usize).or_insert_with(|| {
-                    let lbl = format!("lbl{}", next_label);
-                    *next_label += 1;
-                    lbl
-                });
-                origins.insert(ip + 1);
-            },
+    // runs of `out` with immediate operands become `.out "..."`; a label
+    //   inside a run splits it, since something may jump there
+    fn fold_out(stmts: Vec<(usize, AsmItem)>, labels: &Labels
+      ) -> Vec<(usize, AsmItem)> {
+        fn flush(run: &mut Vec<(usize, u16)>, out: &mut Vec<(usize, AsmItem)>) {
+            match run.as_slice() {
+                [] => { },
+                [(ip, c)] => out.push((*ip, AsmItem::Instruction(
+                  Instruction::Out(SrcOperand::Immediate(*c))))),
+                [(ip, _), ..] => out.push((*ip, AsmItem::String(StringKind::Out,
+                  run.iter().map(|(_, c)| *c).collect()))),
+            };
+            run.clear();
+        }
 
-            Instruction::Jt(_, SrcOperand::Immediate(dst)) => {
-                labels.entry(*dst as usize).or_insert_with(|| {
-                    let lbl = format!("lbl{}", next_label);
-                    *next_label += 1;
-                    lbl
-                });
-                origins.insert(ip + 2);
-            },
+        let mut folded = Vec::with_capacity(stmts.len());
+        let mut run = Vec::new();
+        for (ip, stmt) in stmts {
+            match stmt {
+                AsmItem::Instruction(Instruction::Out(SrcOperand::Immediate(c)))
+                  => {
+                    if labels.contains_key(&ip) {
+                        flush(&mut run, &mut folded);
+                    }
+                    run.push((ip, c));
+                },
+
+                _ => {
+                    flush(&mut run, &mut folded);
+                    folded.push((ip, stmt));
+                },
+            };
+        }
+        flush(&mut run, &mut folded);
+        folded
+    }
 
-            Instruction::Jf(_, SrcOperand::Immediate(dst)) => {
-                labels.entry(*dst as usize).or_insert_with(|| {
-                    let lbl = format!("lbl{}", next_label);
-                    *next_label += 1;
-                    lbl
-                });
-                origins.insert(ip + 2);
+    // how the immediate `value` at `at` renders
+    pub fn word_at(&self, at: usize, value: u16, in_out: bool,
+      opts: &DisAsmOpts) -> Word<'_> {
+        if opts.style.symbolic && self.origins.contains(&at) {
+            if let Some(name) = self.symbol_for(value as usize, opts) {
+                return Word::Symbol(name);
+            }
+        }
+
+        match opts.style.char_literal(value, in_out) {
+            Some(c) => Word::Char(c),
+            None => Word::Number(value),
+        }
+    }
+
+    pub fn write_word<W: Write>(&self, w: &mut W, at: usize, word: u16,
+      in_out: bool, opts: &DisAsmOpts) -> Result<(), DisAsmError> {
+        match self.word_at(at, word, in_out, opts) {
+            Word::Symbol(name) => write!(w, "{}", name)?,
+            Word::Char(c) => write!(w, "'{}'", c)?,
+            Word::Number(n) => write!(w, "{}", opts.style.number(n))?,
+        };
+        Ok(())
+    }
+
+    // an address relative to the nearest label at or before it
+    pub fn describe_addr(&self, addr: usize) -> String {
+        let nearest = self.labels.iter()
+          .filter(|(&a, _)| a <= addr)
+          .max_by_key(|(&a, _)| a);
+        match nearest {
+            Some((&a, name)) if a == addr => format!("{} ({})", name, addr),
+            Some((&a, name)) => format!("{}+{} ({})", name, addr - a, addr),
+            None => addr.to_string(),
+        }
+    }
+
+    // a folded run of `out`s, one instruction per address
+    fn write_expanded_out<W: Write>(&self, w: &mut W, ip: usize, chars: &[u16],
+      opts: &DisAsmOpts) -> Result<(), DisAsmError> {
+        writeln!(w, "; .out \"{}\"", escape_string(chars))?;
+        for (i, c) in chars.iter().enumerate() {
+            let addr = ip + 2 * i;
+            let instr = Instruction::Out(SrcOperand::Immediate(*c));
+            write!(w, "{}\t", addr)?;
+            AsmItem::Instruction(instr).disasm(addr, self, opts, w)?;
+        }
+        Ok(())
+    }
+
+    // the comment lines the user attached to an address, without the `;`
+    pub fn annotations(&self, addr: usize, opts: &DisAsmOpts) -> Vec<String> {
+        let mut lines = Vec::new();
+        if opts.entries.contains(&addr) {
+            lines.push("entry".to_string());
+        }
+        for region in opts.regions.iter().filter(|r| r.start == addr) {
+            if let RegionKind::Struct(name) = &region.kind {
+                lines.push(format!("struct {} ({} words)", name, region.len));
+            }
+        }
+        if let Some(sig) = opts.signatures.get(&addr) {
+            lines.push(sig.clone());
+        }
+        let comments = opts.comments.get(&addr).into_iter().flatten();
+        lines.extend(comments.flat_map(|text| text.lines()).map(String::from));
+        lines
+    }
+
+    fn write_annotations<W: Write>(&self, w: &mut W, addr: usize,
+      opts: &DisAsmOpts) -> Result<(), DisAsmError> {
+        for line in self.annotations(addr, opts) {
+            writeln!(w, "; {}", line)?;
+        }
+        Ok(())
+    }
+
+    fn write_xrefs<W: Write>(&self, w: &mut W, addr: usize
+      ) -> Result<(), DisAsmError> {
+        if let Some(refs) = self.xrefs.get(&addr) {
+            for chunk in refs.chunks(4) {
+                let descs: Vec<_> = chunk.iter()
+                  .map(|x| format!("{} from {}", x.kind,
+                    self.describe_addr(x.from)))
+                  .collect();
+                writeln!(w, "; xref: {}", descs.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+
+    // the memory address an instruction reads or writes, if known
+    //   statically, with the address of the word that supplies it
+    fn data_access(ip: usize, instr: &Instruction, consts: &RegConsts
+      ) -> Option<(u16, XrefKind, usize)> {
+        match *instr {
+            Instruction::Rmem(_, SrcOperand::Immediate(addr)) =>
+              Some((addr, XrefKind::Read, ip + 2)),
+            Instruction::Rmem(_, SrcOperand::Register(r)) =>
+              consts[r].map(|(addr, origin)| (addr, XrefKind::Read, origin)),
+            Instruction::Wmem(SrcOperand::Immediate(addr), _) =>
+              Some((addr, XrefKind::Write, ip + 1)),
+            Instruction::Wmem(SrcOperand::Register(r), _) =>
+              consts[r].map(|(addr, origin)| (addr, XrefKind::Write, origin)),
+            _ => None,
+        }
+    }
+
+    // follow constants loaded into registers through straight-line code
+    fn track_consts(ip: usize, instr: &Instruction, consts: &mut RegConsts) {
+        if instr.info().transfers_control() {
+            *consts = [None; 8];
+            return;
+        }
+
+        for r in instr.effects(ip).writes.iter() {
+            consts[r] = None;
+        }
+        if let Instruction::Set(dst, SrcOperand::Immediate(val)) = *instr {
+            consts[dst.register()] = Some((val, ip + 2));
+        }
+    }
+
+    fn add_xrefs(ip: usize, instr: &Instruction,
+      access: Option<(u16, XrefKind, usize)>, xrefs: &mut Xrefs) {
+        let (addr, kind) = match (access, instr.info().flow, instr.target()) {
+            (Some((addr, kind, _)), _, _) => (addr, kind),
+            (_, FlowClass::Call, Some(SrcOperand::Immediate(addr))) =>
+              (addr, XrefKind::Call),
+            (_, FlowClass::Jump | FlowClass::Branch,
+              Some(SrcOperand::Immediate(addr))) => (addr, XrefKind::Jump),
+            _ => return,
+        };
+        xrefs.entry(addr as usize).or_default()
+          .push(Xref { from: ip, kind });
+    }
+
+    // the label to render for an address, if any; round-trip output may
+    //   only use names the assembler can parse back unambiguously
+    pub fn symbol_for(&self, addr: usize, opts: &DisAsmOpts) -> Option<&str> {
+        let name = self.labels.get(&addr)?;
+        if !opts.round_trip {
+            return Some(name);
+        }
+
+        let first_owner = self.labels.iter()
+          .filter(|(_, n)| *n == name)
+          .map(|(a, _)| *a)
+          .min();
+        if is_valid_symbol(name) && first_owner == Some(addr) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    // labels which don't start a statement can't be defined inline, so
+    //   round-trip output defines them as constants up front
+    fn write_label_constants<W: Write>(&self, w: &mut W, opts: &DisAsmOpts
+      ) -> Result<(), DisAsmError> {
+        let starts: HashSet<usize> = self.stmts.iter()
+          .map(|(ip, _)| *ip)
+          .collect();
+        let mut addrs: Vec<usize> = self.labels.keys()
+          .filter(|addr| !starts.contains(addr))
+          .copied()
+          .collect();
+        addrs.sort_unstable();
+
+        for addr in addrs {
+            if let Some(name) = self.symbol_for(addr, opts) {
+                writeln!(w, "{} = {}", name, addr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn detect_string(memory: &[u16], ip: usize, region: Option<&Region>,
+      labels: &Labels, opts: &DisAsmOpts) -> Option<AsmItem> {
+        match opts.string_hints.get(&ip) {
+            Some(StringHint::Never) => return None,
+
+            Some(StringHint::Prefixed) => {
+                // a length running off the end isn't a string we can render
+                let end = ip + 1 + memory[ip] as usize;
+                if end > memory.len() {
+                    return None;
+                }
+                let chars = memory[ip + 1..end].to_vec();
+                return Some(AsmItem::String(StringKind::Prefixed, chars));
             },
 
-            Instruction::Call(SrcOperand::Immediate(dst)) => {
-                labels.entry(*dst as usize).or_insert_with(|| {
-                    let lbl = format!("fn{}", next_label);
-                    *next_label += 1;
-                    lbl
-                });
-                origins.insert(ip + 1);
+            // an empty run would never move past `ip`; decode it instead
+            Some(StringHint::Raw(len)) if *len > 0 => {
+                let end = (ip + len).min(memory.len());
+                let chars = memory[ip..end].to_vec();
+                return Some(AsmItem::String(StringKind::Raw, chars));
             },
 
+            Some(StringHint::Raw(_)) | None => { },
+        };
+
+        match region {
+            Some(r) if r.kind == RegionKind::String && r.start == ip => {
+                let end = r.range().end.min(memory.len());
+                let chars = memory[ip..end].to_vec();
+                return Some(AsmItem::String(StringKind::Raw, chars));
+            },
+            Some(r) if r.kind == RegionKind::Code => return None,
             _ => { },
+        };
+
+        if !opts.strings {
+            return None;
+        }
+
+        let len = memory[ip] as usize;
+        if len > 0 && ip + 1 + len <= memory.len() {
+            let chars = &memory[ip + 1..ip + 1 + len];
+            if chars.iter().all(|&c| is_text_char(c))
+              && string_confidence(chars, StringKind::Prefixed)
+                >= opts.string_threshold {
+                return Some(
+                  AsmItem::String(StringKind::Prefixed, chars.to_vec()));
+            }
+        }
+
+        // runs must start on a printable word, or we'd eat '\n' opcodes
+        if !is_printable_char(memory[ip]) {
+            return None;
+        }
+
+        let mut end = ip + 1;
+        while end < memory.len() && is_text_char(memory[end])
+          && !labels.contains_key(&end)
+          && !opts.string_hints.contains_key(&end)
+          && !Self::prefixed_string_at(memory, end, opts) {
+            end += 1;
+        }
+
+        let chars = &memory[ip..end];
+        if string_confidence(chars, StringKind::Raw) >= opts.string_threshold {
+            Some(AsmItem::String(StringKind::Raw, chars.to_vec()))
+        } else {
+            None
+        }
+    }
+
+    fn prefixed_string_at(memory: &[u16], ip: usize, opts: &DisAsmOpts
+      ) -> bool {
+        let len = memory[ip] as usize;
+        len > 0 && ip + 1 + len <= memory.len()
+          && memory[ip + 1..ip + 1 + len].iter().all(|&c| is_text_char(c))
+          && string_confidence(&memory[ip + 1..ip + 1 + len],
+               StringKind::Prefixed) >= opts.string_threshold
+    }
+
+    fn add_labels(ip: usize, instr: &Instruction,
+      access: Option<(u16, XrefKind, usize)>,
+      labels: &mut Labels, origins: &mut HashSet<usize>,
+      next_label: &mut usize) {
+        let mut add = |addr: u16, prefix: &str, origin: usize| {
+            labels.entry(addr as usize).or_insert_with(|| {
+                let lbl = format!("{}{}", prefix, next_label);
+                *next_label += 1;
+                lbl
+            });
+            origins.insert(origin);
+        };
+
+        if let Some((addr, _, origin)) = access {
+            add(addr, "data", origin);
+        }
+
+        let info = instr.info();
+        if let (Some(i), Some(SrcOperand::Immediate(dst))) =
+          (info.target, instr.target()) {
+            let prefix = match info.flow {
+                FlowClass::Call => "fn",
+                _ => "lbl",
+            };
+            add(dst, prefix, ip + 1 + i);
         }
     }
 }
@@ -181,6 +667,18 @@ pub struct DisAsmOpts {
     pub autolabel: bool,
     pub line_addrs: bool,
     pub initial_labels: Option<Labels>,
+    pub strings: bool,
+    pub string_threshold: f32,
+    pub string_hints: StringHints,
+    pub highlights: Vec<Range<usize>>,
+    pub round_trip: bool,
+    pub xrefs: bool,
+    pub comments: Comments,
+    pub signatures: Signatures,
+    pub regions: Vec<Region>,
+    pub entries: Entries,
+    pub style: DisAsmStyle,
+    pub fold_out: bool,
 }
 
 impl Default for DisAsmOpts {
@@ -189,190 +687,269 @@ impl Default for DisAsmOpts {
             autolabel: true,
             line_addrs: false,
             initial_labels: None,
+            strings: false,
+            string_threshold: DEFAULT_STRING_THRESHOLD,
+            string_hints: HashMap::new(),
+            highlights: Vec::new(),
+            round_trip: false,
+            xrefs: false,
+            comments: HashMap::new(),
+            signatures: HashMap::new(),
+            regions: Vec::new(),
+            entries: BTreeSet::new(),
+            style: DisAsmStyle::default(),
+            fold_out: false,
         }
     }
 }
 
-pub trait DisAsm {
-    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap, w: &mut W
-      ) -> Result<(), DisAsmError>;
+pub const DEFAULT_STRING_THRESHOLD: f32 = 0.75;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    Hex,
+    Octal,
 }
 
-impl DisAsm for Instruction {
-    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap, w: &mut W
-      ) -> Result<(), DisAsmError> {
-        match self {
-            Instruction::Halt => write!(w, "halt\n")?,
-
-            Instruction::Set(dst, src) => {
-                write!(w, "set ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                src.disasm(ip + 2, map, w)?;
-                write!(w, "\n")?;
-            },
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CharLiterals {
+    Always,
+    Never,
+    // only for operands of `out`
+    OutOnly,
+}
 
-            Instruction::Push(src) => {
-                write!(w, "push ")?;
-                src.disasm(ip + 1, map, w)?;
-                write!(w, "\n")?;
-            },
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterNames {
+    // r0
+    Short,
+    // R0
+    Upper,
+    // reg0
+    Long,
+}
 
-            Instruction::Pop(dst) => {
-                write!(w, "pop ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, "\n")?;
-            },
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DisAsmStyle {
+    pub radix: Radix,
+    pub chars: CharLiterals,
+    pub registers: RegisterNames,
+    // render labeled operands by name, rather than by address
+    pub symbolic: bool,
+    // pad labels and mnemonics out to columns
+    pub align: bool,
+    pub uppercase: bool,
+}
 
-            Instruction::Eq(dst, lhs, rhs) => {
-                write!(w, "eq ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                lhs.disasm(ip + 2, map, w)?;
-                write!(w, ", ")?;
-                rhs.disasm(ip + 3, map, w)?;
-                write!(w, "\n")?;
-            },
+impl Default for DisAsmStyle {
+    fn default() -> Self {
+        Self {
+            radix: Radix::Decimal,
+            chars: CharLiterals::Always,
+            registers: RegisterNames::Short,
+            symbolic: true,
+            align: false,
+            uppercase: false,
+        }
+    }
+}
 
-            Instruction::Gt(dst, lhs, rhs) => {
-                write!(w, "gt ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                lhs.disasm(ip + 2, map, w)?;
-                write!(w, ", ")?;
-                rhs.disasm(ip + 3, map, w)?;
-                write!(w, "\n")?;
-            },
+impl DisAsmStyle {
+    pub fn mnemonic(&self, mnemonic: &str) -> String {
+        if self.uppercase {
+            mnemonic.to_ascii_uppercase()
+        } else {
+            mnemonic.to_string()
+        }
+    }
 
-            Instruction::Jmp(dst) => {
-                write!(w, "jmp ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, "\n")?;
-            },
+    pub fn register(&self, reg: usize) -> String {
+        match self.registers {
+            RegisterNames::Short => format!("r{}", reg),
+            RegisterNames::Upper => format!("R{}", reg),
+            RegisterNames::Long => format!("reg{}", reg),
+        }
+    }
 
-            Instruction::Jt(cond, dst) => {
-                write!(w, "jt ")?;
-                cond.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                dst.disasm(ip + 2, map, w)?;
-                write!(w, "\n")?;
-            },
+    pub fn number(&self, word: u16) -> String {
+        match self.radix {
+            Radix::Decimal => word.to_string(),
+            Radix::Hex => format!("0x{:x}", word),
+            Radix::Octal => format!("0o{:o}", word),
+        }
+    }
 
-            Instruction::Jf(cond, dst) => {
-                write!(w, "jf ")?;
-                cond.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                dst.disasm(ip + 2, map, w)?;
-                write!(w, "\n")?;
-            },
+    // the character a word should be rendered as, if any
+    pub fn char_literal(&self, word: u16, in_out: bool) -> Option<char> {
+        let allowed = match self.chars {
+            CharLiterals::Always => true,
+            CharLiterals::Never => false,
+            CharLiterals::OutOnly => in_out,
+        };
+        let word_u8 = word as u8;
+        if allowed && word & vm::VALID_IO_MASK == 0
+          && word_u8.is_ascii() && !word_u8.is_ascii_control() {
+            Some(word_u8 as char)
+        } else {
+            None
+        }
+    }
+}
 
-            Instruction::Add(dst, lhs, rhs) => {
-                write!(w, "add ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                lhs.disasm(ip + 2, map, w)?;
-                write!(w, ", ")?;
-                rhs.disasm(ip + 3, map, w)?;
-                write!(w, "\n")?;
-            },
+// a comma-separated list of `radix=dec|hex|oct`, `chars=always|never|out`,
+//   `regs=r|R|reg`, `addrs`, `align` and `upper`
+impl FromStr for DisAsmStyle {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut style = DisAsmStyle::default();
+        for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            match item {
+                "radix=dec" => style.radix = Radix::Decimal,
+                "radix=hex" => style.radix = Radix::Hex,
+                "radix=oct" => style.radix = Radix::Octal,
+                "chars=always" => style.chars = CharLiterals::Always,
+                "chars=never" => style.chars = CharLiterals::Never,
+                "chars=out" => style.chars = CharLiterals::OutOnly,
+                "regs=r" => style.registers = RegisterNames::Short,
+                "regs=R" => style.registers = RegisterNames::Upper,
+                "regs=reg" => style.registers = RegisterNames::Long,
+                "addrs" => style.symbolic = false,
+                "align" => style.align = true,
+                "upper" => style.uppercase = true,
+                _ => return Err(format!("unknown style option \"{}\"", item)),
+            };
+        }
+        Ok(style)
+    }
+}
 
-            Instruction::Mult(dst, lhs, rhs) => {
-                write!(w, "mult ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                lhs.disasm(ip + 2, map, w)?;
-                write!(w, ", ")?;
-                rhs.disasm(ip + 3, map, w)?;
-                write!(w, "\n")?;
-            },
+// r0-r7, also spelled R0 or reg0
+pub fn parse_register(name: &str) -> Option<usize> {
+    let lower = name.to_ascii_lowercase();
+    let digits = lower.strip_prefix("reg")
+      .or_else(|| lower.strip_prefix('r'))?;
+    match digits.as_bytes() {
+        [n @ b'0'..=b'7'] => Some((n - b'0') as usize),
+        _ => None,
+    }
+}
 
-            Instruction::Mod(dst, lhs, rhs) => {
-                write!(w, "mod ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                lhs.disasm(ip + 2, map, w)?;
-                write!(w, ", ")?;
-                rhs.disasm(ip + 3, map, w)?;
-                write!(w, "\n")?;
-            },
+pub fn is_valid_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_ok = match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || c == '_',
+        None => false,
+    };
+    starts_ok && parse_register(name).is_none()
+      && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
 
-            Instruction::And(dst, lhs, rhs) => {
-                write!(w, "and ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                lhs.disasm(ip + 2, map, w)?;
-                write!(w, ", ")?;
-                rhs.disasm(ip + 3, map, w)?;
-                write!(w, "\n")?;
-            },
+#[inline]
+fn is_printable_char(word: u16) -> bool {
+    (0x20..0x7f).contains(&word)
+}
 
-            Instruction::Or(dst, lhs, rhs) => {
-                write!(w, "or ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                lhs.disasm(ip + 2, map, w)?;
-                write!(w, ", ")?;
-                rhs.disasm(ip + 3, map, w)?;
-                write!(w, "\n")?;
-            },
+#[inline]
+fn is_text_char(word: u16) -> bool {
+    is_printable_char(word) || word == b'\n' as u16 || word == b'\t' as u16
+      || word == b'\r' as u16
+}
 
-            Instruction::Not(dst, src) => {
-                write!(w, "not ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                src.disasm(ip + 2, map, w)?;
-                write!(w, "\n")?;
-            },
+// a score in [0, 1]: the fraction of "wordy" characters, discounted for
+//   short strings, where coincidence is likelier; a length prefix which
+//   exactly covers a run of text is evidence in itself, so those are
+//   discounted less
+pub fn string_confidence(chars: &[u16], kind: StringKind) -> f32 {
+    if chars.is_empty() {
+        return 0.0;
+    }
 
-            Instruction::Rmem(dst, src) => {
-                write!(w, "rmem ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                src.disasm(ip + 2, map, w)?;
-                write!(w, "\n")?;
-            },
+    let wordy = chars.iter()
+      .filter(|&&c| c & vm::VALID_IO_MASK == 0)
+      .map(|&c| c as u8)
+      .filter(|c| c.is_ascii_alphanumeric() || b" \n.,;:!?'\"-()".contains(c))
+      .count();
+    let quality = wordy as f32 / chars.len() as f32;
 
-            Instruction::Wmem(dst, src) => {
-                write!(w, "wmem ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, ", ")?;
-                src.disasm(ip + 2, map, w)?;
-                write!(w, "\n")?;
-            },
+    let n = chars.len() as f32;
+    let length = match kind {
+        StringKind::Prefixed => 1.0 - 1.0 / (n + 2.0),
+        StringKind::Raw | StringKind::Out => 1.0 - 1.0 / n,
+    };
 
-            Instruction::Call(dst) => {
-                write!(w, "call ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, "\n")?;
-            },
+    quality * length
+}
 
-            Instruction::Ret => write!(w, "ret\n")?,
+pub fn escape_string(chars: &[u16]) -> String {
+    let mut escaped = String::new();
+    for &c in chars {
+        match c {
+            0x5c => escaped.push_str("\\\\"),
+            0x22 => escaped.push_str("\\\""),
+            0x0a => escaped.push_str("\\n"),
+            0x09 => escaped.push_str("\\t"),
+            0x0d => escaped.push_str("\\r"),
+            c if is_printable_char(c) => escaped.push(c as u8 as char),
+            c => escaped.push_str(&format!("\\u{{{:x}}}", c)),
+        }
+    }
+    escaped
+}
 
-            Instruction::Out(src) => {
-                write!(w, "out ")?;
-                src.disasm(ip + 1, map, w)?;
-                write!(w, "\n")?;
-            },
+pub trait DisAsm {
+    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap,
+      opts: &DisAsmOpts, w: &mut W
+      ) -> Result<(), DisAsmError>;
+}
 
-            Instruction::In(dst) => {
-                write!(w, "in ")?;
-                dst.disasm(ip + 1, map, w)?;
-                write!(w, "\n")?;
-            },
+impl DisAsm for Instruction {
+    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap,
+      opts: &DisAsmOpts, w: &mut W
+      ) -> Result<(), DisAsmError> {
+        let style = &opts.style;
+        let mnemonic = style.mnemonic(self.info().mnemonic);
+        let operands = self.operands();
+        if style.align && !operands.is_empty() {
+            write!(w, "{:<5}", mnemonic)?;
+        } else {
+            write!(w, "{}", mnemonic)?;
+        }
 
-            Instruction::Noop => write!(w, "noop\n")?,
-        };
+        let in_out = matches!(self, Instruction::Out(_));
+        for (i, operand) in operands.iter().enumerate() {
+            write!(w, "{}", if i == 0 { " " } else { ", " })?;
+            match operand {
+                Operand::Src(SrcOperand::Immediate(word)) =>
+                  map.write_word(w, ip + 1 + i, *word, in_out, opts)?,
+                _ => operand.disasm(ip + 1 + i, map, opts, w)?,
+            };
+        }
+        writeln!(w)?;
         Ok(())
     }
 }
 
+impl DisAsm for Operand {
+    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap,
+      opts: &DisAsmOpts, w: &mut W
+      ) -> Result<(), DisAsmError> {
+        match self {
+            Operand::Src(src) => src.disasm(ip, map, opts, w),
+            Operand::Dst(dst) => dst.disasm(ip, map, opts, w),
+        }
+    }
+}
+
 impl DisAsm for SrcOperand {
-    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap, w: &mut W
+    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap,
+      opts: &DisAsmOpts, w: &mut W
       ) -> Result<(), DisAsmError> {
         match self {
-            SrcOperand::Immediate(word) => word.disasm(ip, map, w),
+            SrcOperand::Immediate(word) => word.disasm(ip, map, opts, w),
             SrcOperand::Register(n) => {
-                write!(w, "r{}", n)?;
+                write!(w, "{}", opts.style.register(*n))?;
                 Ok(())
             }
         }
@@ -380,26 +957,48 @@ impl DisAsm for SrcOperand {
 }
 
 impl DisAsm for DstOperand {
-    fn disasm<W: Write>(&self, _ip: usize, _map: &ImageMap, w: &mut W
+    fn disasm<W: Write>(&self, _ip: usize, _map: &ImageMap,
+      opts: &DisAsmOpts, w: &mut W
       ) -> Result<(), DisAsmError> {
         match self {
-            DstOperand::Register(n) => write!(w, "r{}", n)?,
+            DstOperand::Register(n) =>
+              write!(w, "{}", opts.style.register(*n))?,
         };
         Ok(())
     }
 }
 
 impl DisAsm for AsmItem {
-    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap, w: &mut W
+    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap,
+      opts: &DisAsmOpts, w: &mut W
       ) -> Result<(), DisAsmError> {
-        if let Some(label) = map.labels.get(&ip) {
-            write!(w, "{}: ", label)?;
-        }
-        match *self {
-            AsmItem::Instruction(instr) => instr.disasm(ip, map, w),
+        let width = if opts.style.align { map.label_width } else { 0 };
+        match map.labels.get(&ip) {
+            Some(label) if map.symbol_for(ip, opts).is_some() =>
+              write!(w, "{:<1$}", format!("{}: ", label), width)?,
+
+            Some(label) => {
+                writeln!(w, "; {}:", label)?;
+                write!(w, "{:1$}", "", width)?;
+            },
+
+            None => write!(w, "{:1$}", "", width)?,
+        };
+
+        match self {
+            AsmItem::Instruction(instr) => instr.disasm(ip, map, opts, w),
+            AsmItem::Value(word) if opts.round_trip => {
+                writeln!(w, ".word {}", opts.style.number(*word))?;
+                Ok(())
+            },
             AsmItem::Value(word) => {
-                word.disasm(ip, map, w)?;
-                write!(w, "\n")?;
+                word.disasm(ip, map, opts, w)?;
+                writeln!(w)?;
+                Ok(())
+            },
+            AsmItem::String(kind, chars) => {
+                writeln!(w, "{} \"{}\"", kind.directive(),
+                  escape_string(chars))?;
                 Ok(())
             },
         }
@@ -407,38 +1006,13 @@ impl DisAsm for AsmItem {
 }
 
 impl DisAsm for u16 {
-    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap, w: &mut W
+    fn disasm<W: Write>(&self, ip: usize, map: &ImageMap,
+      opts: &DisAsmOpts, w: &mut W
       ) -> Result<(), DisAsmError> {
-        if map.origins.contains(&ip) {
-            if let Some(lbl) = map.labels.get(&(*self as usize)) {
-                write!(w, "{}", lbl)?;
-                return Ok(());
-            }
-        }
-
-        let word_u8 = *self as u8;
-        if *self & vm::VALID_IO_MASK == 0
-          && word_u8.is_ascii() && !word_u8.is_ascii_control() {
-            write!(w, "'{}'", word_u8 as char)?;
-        } else {
-            write!(w, "{}", *self)?;
-        }
-        Ok(())
+        map.write_word(w, ip, *self, false, opts)
     }
 }
 
 pub fn read_labels<R: BufRead>(r: &mut R) -> Result<Labels, AsmError> {
-    let mut labels = HashMap::new();
-    for line in r.lines() {
-        let line = line?;
-        let mut iter = line.split('\t');
-        let addr = iter.next()
-          .ok_or_else(|| AsmError::LabelFileSyntaxError(line.to_string()))?
-          .parse()
-          .map_err(|_| AsmError::LabelFileSyntaxError(line.to_string()))?;
-        let name = iter.next()
-          .ok_or_else(|| AsmError::LabelFileSyntaxError(line.to_string()))?;
-        labels.insert(addr, name.to_string());
-    }
-    Ok(labels)
+    Ok(ProjectMap::read(r)?.labels)
 }
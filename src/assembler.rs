@@ -0,0 +1,888 @@
+use std::{
+    collections::HashMap,
+    fs,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::CharIndices,
+};
+
+use super::{
+    asm::{AsmError, Expansion, SourcePos, StringKind, parse_register},
+    isa::{self, OperandKind},
+    vm::{DstOperand, INDIRECT_BIT, Instruction, SrcOperand},
+};
+
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+const WORD_MODULUS: u32 = INDIRECT_BIT as u32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(u16),
+    Str(Vec<u16>),
+    Colon,
+    Comma,
+    Equals,
+    Here,
+    LParen,
+    RParen,
+    Op(&'static str),
+}
+
+type Line = Vec<(SourcePos, Token)>;
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(u16),
+    Symbol(String),
+    Here,
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Register(usize),
+    Expr(Expr),
+}
+
+#[derive(Clone, Debug)]
+enum Body {
+    Instruction(u16, Vec<(SourcePos, Operand)>),
+    Words(Vec<(SourcePos, Expr)>),
+    String(StringKind, Vec<u16>),
+}
+
+impl Body {
+    fn size(&self) -> usize {
+        match self {
+            Body::Instruction(_, operands) => 1 + operands.len(),
+            Body::Words(exprs) => exprs.len(),
+            Body::String(StringKind::Prefixed, chars) => 1 + chars.len(),
+            Body::String(StringKind::Raw, chars) => chars.len(),
+            Body::String(StringKind::Out, chars) => 2 * chars.len(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Stmt {
+    pos: SourcePos,
+    addr: usize,
+    scope: Option<String>,
+    body: Body,
+}
+
+#[derive(Clone, Debug)]
+enum Symbol {
+    Label(usize),
+    // with the address it was defined at, for `$`
+    Const(SourcePos, Option<String>, usize, Expr),
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Line>,
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u16>, AsmError> {
+    Assembler::new(PathBuf::from(".")).assemble(source, None)
+}
+
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u16>, AsmError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    Assembler::new(dir).assemble(&source, Some(path.display().to_string()))
+}
+
+struct Assembler {
+    base_dir: PathBuf,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Assembler {
+    fn new(base_dir: PathBuf) -> Self {
+        Self {
+            base_dir,
+            macros: HashMap::new(),
+            expansions: 0,
+            symbols: HashMap::new(),
+        }
+    }
+
+    fn assemble(mut self, source: &str, file: Option<String>
+      ) -> Result<Vec<u16>, AsmError> {
+        let lines = tokenize_source(source, file.as_deref())?;
+        let mut expanded = Vec::new();
+        self.preprocess(lines, &mut expanded, 0)?;
+        let stmts = self.layout(&expanded)?;
+        self.emit(&stmts)
+    }
+
+    // handle .include, .macro and macro invocations, leaving only
+    //   statements for layout
+    fn preprocess(&mut self, lines: Vec<Line>, out: &mut Vec<Line>,
+      depth: usize) -> Result<(), AsmError> {
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            let label_len = line.iter()
+              .position(|(_, tok)| *tok == Token::Colon)
+              .filter(|&i| i == 1)
+              .map_or(0, |i| i + 1);
+            let (labels, rest) = line.split_at(label_len);
+
+            match rest.first() {
+                Some((pos, Token::Ident(d))) if d == ".include" => {
+                    if depth >= MAX_EXPANSION_DEPTH {
+                        return Err(syntax_error(pos,
+                          "includes nested too deeply".to_string()));
+                    }
+                    let path = match &rest[1..] {
+                        [(_, Token::Str(path))] => String::from_utf16(path)
+                          .map_err(|_| syntax_error(pos,
+                            "invalid include path".to_string()))?,
+                        _ => return Err(syntax_error(pos,
+                          "expected a path string".to_string())),
+                    };
+                    // relative to the including file, if there is one
+                    let dir = pos.file.as_ref()
+                      .and_then(|f| Path::new(f).parent())
+                      .unwrap_or(&self.base_dir);
+                    let path = dir.join(path);
+                    let source = fs::read_to_string(&path)?;
+                    let included = tokenize_source(&source,
+                      Some(&path.display().to_string()))?;
+                    if !labels.is_empty() {
+                        out.push(labels.to_vec());
+                    }
+                    self.preprocess(included, out, depth + 1)?;
+                },
+
+                Some((pos, Token::Ident(d))) if d == ".macro" => {
+                    let (name, params) = parse_macro_header(pos, &rest[1..])?;
+                    if isa::by_mnemonic(&name).is_some() {
+                        return Err(syntax_error(pos,
+                          format!("macro \"{}\" shadows a mnemonic", name)));
+                    }
+
+                    let mut body = Vec::new();
+                    loop {
+                        let line = lines.next().ok_or_else(|| syntax_error(pos,
+                          format!("unterminated macro \"{}\"", name)))?;
+                        match line.first() {
+                            Some((_, Token::Ident(d))) if d == ".endm" => break,
+                            Some((pos, Token::Ident(d))) if d == ".macro" =>
+                              return Err(syntax_error(pos,
+                                "nested macro definition".to_string())),
+                            _ => body.push(line),
+                        };
+                    }
+
+                    if self.macros.insert(name.clone(), Macro { params, body })
+                      .is_some() {
+                        return Err(syntax_error(pos,
+                          format!("duplicate macro \"{}\"", name)));
+                    }
+                },
+
+                Some((pos, Token::Ident(d))) if d == ".endm" =>
+                  return Err(syntax_error(pos,
+                    "\".endm\" outside of a macro".to_string())),
+
+                Some((pos, Token::Ident(name)))
+                  if self.macros.contains_key(name) => {
+                    if depth >= MAX_EXPANSION_DEPTH {
+                        return Err(syntax_error(pos,
+                          "macros nested too deeply".to_string()));
+                    }
+                    let expansion = self.expand(pos, name, &rest[1..])?;
+                    if !labels.is_empty() {
+                        out.push(labels.to_vec());
+                    }
+                    self.preprocess(expansion, out, depth + 1)?;
+                },
+
+                _ => out.push(line),
+            };
+        }
+        Ok(())
+    }
+
+    fn expand(&mut self, site: &SourcePos, name: &str, args: &[(SourcePos, Token)]
+      ) -> Result<Vec<Line>, AsmError> {
+        let mac = &self.macros[name];
+        let args: Vec<_> = if args.is_empty() {
+            Vec::new()
+        } else {
+            args.split(|(_, tok)| *tok == Token::Comma).collect()
+        };
+        if args.len() != mac.params.len() {
+            return Err(syntax_error(site, format!(
+              "macro \"{}\" takes {} arguments", name, mac.params.len())));
+        }
+        if args.iter().any(|a| a.is_empty()) {
+            return Err(syntax_error(site, "empty macro argument".to_string()));
+        }
+
+        // local labels defined in the body get a fresh name per expansion
+        self.expansions += 1;
+        let locals: Vec<&String> = mac.body.iter()
+          .filter_map(|line| match &line[..] {
+              [(_, Token::Ident(lbl)), (_, Token::Colon), ..]
+                if lbl.starts_with('.') => Some(lbl),
+              _ => None,
+          })
+          .collect();
+
+        let at = Expansion { name: name.to_string(), site: site.clone() };
+        let mut expanded = Vec::new();
+        for line in &mac.body {
+            let mut new_line = Vec::new();
+            for (pos, tok) in line {
+                let pos = pos.expanded_at(&at);
+                match tok {
+                    Token::Ident(id) => {
+                        if let Some(i) = mac.params.iter().position(|p| p == id) {
+                            new_line.extend(args[i].iter().cloned());
+                        } else if locals.contains(&id) {
+                            new_line.push((pos, Token::Ident(
+                              format!("{}__{}", id, self.expansions))));
+                        } else {
+                            new_line.push((pos, tok.clone()));
+                        }
+                    },
+                    _ => new_line.push((pos, tok.clone())),
+                };
+            }
+            expanded.push(new_line);
+        }
+        Ok(expanded)
+    }
+
+    // assign addresses and define labels and constants
+    fn layout(&mut self, lines: &[Line]) -> Result<Vec<Stmt>, AsmError> {
+        let mut stmts = Vec::new();
+        let mut scope: Option<String> = None;
+        let mut addr = 0;
+
+        for line in lines {
+            let mut toks = &line[..];
+
+            while let [(pos, Token::Ident(name)), (_, Token::Colon), rest @ ..] =
+              toks {
+                if !name.starts_with('.') {
+                    scope = Some(name.clone());
+                }
+                let name = qualify(name, scope.as_deref(), pos)?;
+                self.define(pos, name, Symbol::Label(addr))?;
+                toks = rest;
+            }
+
+            let (pos, first) = match toks.first() {
+                Some((pos, tok)) => (pos.clone(), tok),
+                None => continue,
+            };
+
+            let body = match first {
+                Token::Ident(name) if toks.get(1).map(|(_, t)| t)
+                  == Some(&Token::Equals) => {
+                    let expr = parse_expr(&toks[2..], &pos)?;
+                    self.define_const(&pos, name, scope.as_deref(), addr,
+                      expr)?;
+                    continue;
+                },
+
+                Token::Ident(d) if d == ".equ" || d == ".const" => {
+                    match &toks[1..] {
+                        [(_, Token::Ident(name)), (_, Token::Comma), rest @ ..]
+                          => {
+                            let expr = parse_expr(rest, &pos)?;
+                            self.define_const(&pos, name, scope.as_deref(),
+                              addr, expr)?;
+                        },
+                        _ => return Err(syntax_error(&pos, format!(
+                          "expected \"{} name, value\"", d))),
+                    };
+                    continue;
+                },
+
+                Token::Ident(d) if d == ".org" => {
+                    let expr = parse_expr(&toks[1..], &pos)?;
+                    addr = self.eval(&expr, &pos, scope.as_deref(), addr)?
+                      as usize;
+                    continue;
+                },
+
+                Token::Ident(d) if d == ".word" => {
+                    let mut exprs = Vec::new();
+                    for arg in split_args(&toks[1..], &pos)? {
+                        exprs.push((arg[0].0.clone(), parse_expr(arg, &pos)?));
+                    }
+                    Body::Words(exprs)
+                },
+
+                Token::Ident(d)
+                  if matches!(d.as_str(), ".str" | ".ascii" | ".out") => {
+                    let kind = match d.as_str() {
+                        ".str" => StringKind::Prefixed,
+                        ".ascii" => StringKind::Raw,
+                        _ => StringKind::Out,
+                    };
+                    match &toks[1..] {
+                        [(_, Token::Str(chars))] =>
+                          Body::String(kind, chars.clone()),
+                        _ => return Err(syntax_error(&pos,
+                          "expected a single string literal".to_string())),
+                    }
+                },
+
+                Token::Ident(d) if d.starts_with('.') =>
+                  return Err(syntax_error(&pos,
+                    format!("unknown directive \"{}\"", d))),
+
+                Token::Ident(name) => {
+                    let info = isa::by_mnemonic(name)
+                      .ok_or_else(|| syntax_error(&pos,
+                        format!("unknown mnemonic \"{}\"", name)))?;
+                    Body::Instruction(info.opcode,
+                      parse_operands(&pos, &toks[1..], info.operands)?)
+                },
+
+                // bare data words, as syndis writes them
+                _ => Body::Words(vec![(pos.clone(), parse_expr(toks, &pos)?)]),
+            };
+
+            let size = body.size();
+            stmts.push(Stmt { pos, addr, scope: scope.clone(), body });
+            addr += size;
+        }
+
+        Ok(stmts)
+    }
+
+    fn emit(&self, stmts: &[Stmt]) -> Result<Vec<u16>, AsmError> {
+        let mut image = Vec::new();
+        let mut written = Vec::new();
+
+        for stmt in stmts {
+            let scope = stmt.scope.as_deref();
+            let mut words = Vec::with_capacity(stmt.body.size());
+            match &stmt.body {
+                Body::Instruction(opcode, operands) => {
+                    let kinds = isa::by_opcode(*opcode).unwrap().operands;
+                    let mut ops = Vec::with_capacity(operands.len());
+                    for ((pos, operand), kind) in operands.iter().zip(kinds) {
+                        ops.push(match (operand, kind) {
+                            (Operand::Register(reg), OperandKind::Dst) =>
+                              isa::Operand::Dst(DstOperand::Register(*reg)),
+                            (Operand::Register(reg), OperandKind::Src) =>
+                              isa::Operand::Src(SrcOperand::Register(*reg)),
+                            (Operand::Expr(expr), _) => {
+                                let val = self.eval(expr, pos, scope,
+                                  stmt.addr)?;
+                                if val >= INDIRECT_BIT {
+                                    return Err(syntax_error(pos, format!(
+                                      "immediate out of range ({})", val)));
+                                }
+                                isa::Operand::Src(SrcOperand::Immediate(val))
+                            },
+                        });
+                    }
+                    words.extend(
+                      Instruction::from_operands(*opcode, &ops)?.encode()?);
+                },
+
+                Body::Words(exprs) => {
+                    for (pos, expr) in exprs {
+                        words.push(self.eval(expr, pos, scope, stmt.addr)?);
+                    }
+                },
+
+                Body::String(StringKind::Out, chars) => {
+                    for &c in chars {
+                        if c >= INDIRECT_BIT {
+                            return Err(syntax_error(&stmt.pos, format!(
+                              "character out of range ({})", c)));
+                        }
+                        let out = Instruction::Out(SrcOperand::Immediate(c));
+                        words.extend(out.encode()?);
+                    }
+                },
+
+                Body::String(kind, chars) => {
+                    if *kind == StringKind::Prefixed {
+                        words.push(chars.len() as u16);
+                    }
+                    words.extend_from_slice(chars);
+                },
+            };
+
+            let end = stmt.addr + words.len();
+            if end > WORD_MODULUS as usize {
+                return Err(syntax_error(&stmt.pos,
+                  format!("statement extends past end of memory ({})", end)));
+            }
+            if end > image.len() {
+                image.resize(end, 0);
+                written.resize(end, false);
+            }
+            for (i, word) in words.into_iter().enumerate() {
+                if written[stmt.addr + i] {
+                    return Err(syntax_error(&stmt.pos, format!(
+                      "overlapping output at address {}", stmt.addr + i)));
+                }
+                image[stmt.addr + i] = word;
+                written[stmt.addr + i] = true;
+            }
+        }
+
+        Ok(image)
+    }
+
+    fn define(&mut self, pos: &SourcePos, name: String, sym: Symbol
+      ) -> Result<(), AsmError> {
+        if self.symbols.contains_key(&name) {
+            return Err(syntax_error(pos,
+              format!("duplicate symbol \"{}\"", name)));
+        }
+        self.symbols.insert(name, sym);
+        Ok(())
+    }
+
+    fn define_const(&mut self, pos: &SourcePos, name: &str,
+      scope: Option<&str>, addr: usize, expr: Expr) -> Result<(), AsmError> {
+        let name = qualify(name, scope, pos)?;
+        self.define(pos, name,
+          Symbol::Const(pos.clone(), scope.map(str::to_string), addr, expr))
+    }
+
+    fn eval(&self, expr: &Expr, pos: &SourcePos, scope: Option<&str>,
+      here: usize) -> Result<u16, AsmError> {
+        self.eval_inner(expr, pos, scope, here, &mut Vec::new())
+          .map(|v| v as u16)
+    }
+
+    fn eval_inner(&self, expr: &Expr, pos: &SourcePos, scope: Option<&str>,
+      here: usize, visiting: &mut Vec<String>) -> Result<u32, AsmError> {
+        let m = WORD_MODULUS;
+        let val = match expr {
+            Expr::Number(n) => *n as u32,
+
+            Expr::Here => here as u32 % m,
+
+            Expr::Symbol(name) => {
+                let name = qualify(name, scope, pos)?;
+                match self.symbols.get(&name) {
+                    Some(Symbol::Label(addr)) => *addr as u32 % m,
+
+                    Some(Symbol::Const(def_pos, def_scope, def_addr, expr))
+                      => {
+                        if visiting.contains(&name) {
+                            return Err(syntax_error(def_pos, format!(
+                              "constant \"{}\" is defined in terms of itself",
+                              name)));
+                        }
+                        visiting.push(name);
+                        let val = self.eval_inner(expr, def_pos,
+                          def_scope.as_deref(), *def_addr, visiting)?;
+                        visiting.pop();
+                        val
+                    },
+
+                    None => return Err(syntax_error(pos,
+                      format!("undefined symbol \"{}\"", name))),
+                }
+            },
+
+            // arithmetic is mod 32768, though bare literals may use the
+            //   full word, e.g. for raw .word data
+            Expr::Unary(op, e) => {
+                let v = self.eval_inner(e, pos, scope, here, visiting)? % m;
+                match *op {
+                    "-" => (m - v) % m,
+                    "~" => !v % m,
+                    _ => v,
+                }
+            },
+
+            Expr::Binary(op, lhs, rhs) => {
+                let l = self.eval_inner(lhs, pos, scope, here, visiting)? % m;
+                let r = self.eval_inner(rhs, pos, scope, here, visiting)? % m;
+                match *op {
+                    "+" => (l + r) % m,
+                    "-" => (l + m - r) % m,
+                    "*" => (l * r) % m,
+                    "/" | "%" if r == 0 => return Err(syntax_error(pos,
+                      "division by zero".to_string())),
+                    "/" => l / r,
+                    "%" => l % r,
+                    "&" => l & r,
+                    "|" => l | r,
+                    "^" => l ^ r,
+                    "<<" => l.checked_shl(r).unwrap_or(0) % m,
+                    ">>" => l.checked_shr(r).unwrap_or(0),
+                    _ => unreachable!("unknown operator {}", op),
+                }
+            },
+        };
+        Ok(val)
+    }
+}
+
+fn qualify(name: &str, scope: Option<&str>, pos: &SourcePos
+  ) -> Result<String, AsmError> {
+    if name.starts_with('.') {
+        match scope {
+            Some(scope) => Ok(format!("{}{}", scope, name)),
+            None => Err(syntax_error(pos, format!(
+              "local label \"{}\" before any global label", name))),
+        }
+    } else {
+        Ok(name.to_string())
+    }
+}
+
+fn parse_macro_header(pos: &SourcePos, toks: &[(SourcePos, Token)]
+  ) -> Result<(String, Vec<String>), AsmError> {
+    let name = match toks.first() {
+        Some((_, Token::Ident(name))) if !name.starts_with('.') => name.clone(),
+        _ => return Err(syntax_error(pos, "expected a macro name".to_string())),
+    };
+
+    let mut params = Vec::new();
+    if toks.len() > 1 {
+        for arg in split_args(&toks[1..], pos)? {
+            match arg {
+                [(_, Token::Ident(p))] => params.push(p.clone()),
+                _ => return Err(syntax_error(&arg[0].0,
+                  "expected a parameter name".to_string())),
+            };
+        }
+    }
+    Ok((name, params))
+}
+
+fn split_args<'a>(toks: &'a [(SourcePos, Token)], pos: &SourcePos
+  ) -> Result<Vec<&'a [(SourcePos, Token)]>, AsmError> {
+    if toks.is_empty() {
+        return Err(syntax_error(pos, "expected arguments".to_string()));
+    }
+    let args: Vec<_> = toks.split(|(_, tok)| *tok == Token::Comma).collect();
+    if args.iter().any(|a| a.is_empty()) {
+        return Err(syntax_error(pos, "empty argument".to_string()));
+    }
+    Ok(args)
+}
+
+fn parse_operands(pos: &SourcePos, tokens: &[(SourcePos, Token)],
+  kinds: &[OperandKind]) -> Result<Vec<(SourcePos, Operand)>, AsmError> {
+    let args = if tokens.is_empty() {
+        Vec::new()
+    } else {
+        split_args(tokens, pos)?
+    };
+    if args.len() != kinds.len() {
+        return Err(syntax_error(pos,
+          format!("expected {} operands", kinds.len())));
+    }
+
+    let mut operands = Vec::new();
+    for (arg, kind) in args.into_iter().zip(kinds) {
+        let op_pos = arg[0].0.clone();
+        let operand = match arg {
+            [(_, Token::Ident(name))] => match parse_register(name) {
+                Some(reg) => Operand::Register(reg),
+                None => Operand::Expr(Expr::Symbol(name.clone())),
+            },
+            _ => Operand::Expr(parse_expr(arg, &op_pos)?),
+        };
+        if *kind == OperandKind::Dst {
+            if let Operand::Register(_) = operand { } else {
+                return Err(syntax_error(&op_pos,
+                  "destination operand must be a register".to_string()));
+            }
+        }
+        operands.push((op_pos, operand));
+    }
+
+    Ok(operands)
+}
+
+const PRECEDENCE: &[&[&str]] = &[
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn parse_expr(toks: &[(SourcePos, Token)], pos: &SourcePos
+  ) -> Result<Expr, AsmError> {
+    let mut toks = toks.iter().peekable();
+    let expr = parse_binary(&mut toks, pos, 0)?;
+    match toks.next() {
+        Some((pos, _)) => Err(syntax_error(pos,
+          "unexpected token in expression".to_string())),
+        None => Ok(expr),
+    }
+}
+
+type TokenIter<'a> = Peekable<std::slice::Iter<'a, (SourcePos, Token)>>;
+
+fn parse_binary(toks: &mut TokenIter, pos: &SourcePos, level: usize
+  ) -> Result<Expr, AsmError> {
+    if level == PRECEDENCE.len() {
+        return parse_unary(toks, pos);
+    }
+
+    let mut lhs = parse_binary(toks, pos, level + 1)?;
+    while let Some((_, Token::Op(op))) = toks.peek() {
+        if !PRECEDENCE[level].contains(op) {
+            break;
+        }
+        toks.next();
+        let rhs = parse_binary(toks, pos, level + 1)?;
+        lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+    Ok(lhs)
+}
+
+fn parse_unary(toks: &mut TokenIter, pos: &SourcePos
+  ) -> Result<Expr, AsmError> {
+    match toks.next() {
+        Some((_, Token::Op(op @ ("-" | "~" | "+")))) =>
+          Ok(Expr::Unary(op, Box::new(parse_unary(toks, pos)?))),
+
+        Some((_, Token::Number(n))) => Ok(Expr::Number(*n)),
+
+        Some((_, Token::Here)) => Ok(Expr::Here),
+
+        Some((pos, Token::Ident(name))) => {
+            if parse_register(name).is_some() {
+                return Err(syntax_error(pos,
+                  "registers can't appear in expressions".to_string()));
+            }
+            Ok(Expr::Symbol(name.clone()))
+        },
+
+        Some((pos, Token::LParen)) => {
+            let expr = parse_binary(toks, pos, 0)?;
+            match toks.next() {
+                Some((_, Token::RParen)) => Ok(expr),
+                _ => Err(syntax_error(pos, "unbalanced \"(\"".to_string())),
+            }
+        },
+
+        Some((pos, _)) =>
+          Err(syntax_error(pos, "expected an expression".to_string())),
+
+        None => Err(syntax_error(pos, "expected an expression".to_string())),
+    }
+}
+
+fn tokenize_source(source: &str, file: Option<&str>
+  ) -> Result<Vec<Line>, AsmError> {
+    let mut lines = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let tokens = tokenize(line, file, i + 1)?;
+        if !tokens.is_empty() {
+            lines.push(tokens);
+        }
+    }
+    Ok(lines)
+}
+
+fn tokenize(line: &str, file: Option<&str>, line_no: usize
+  ) -> Result<Line, AsmError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    let col_of = |byte: usize| line[..byte].chars().count() + 1;
+
+    while let Some(&(i, c)) = chars.peek() {
+        let pos = SourcePos {
+            file: file.map(str::to_string),
+            line: line_no,
+            col: col_of(i),
+            expansion: None,
+        };
+        chars.next();
+
+        let tok = match c {
+            ';' => break,
+
+            c if c.is_whitespace() => continue,
+
+            ':' => Token::Colon,
+            ',' => Token::Comma,
+            '=' => Token::Equals,
+            '$' => Token::Here,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '+' => Token::Op("+"),
+            '-' => Token::Op("-"),
+            '*' => Token::Op("*"),
+            '/' => Token::Op("/"),
+            '%' => Token::Op("%"),
+            '&' => Token::Op("&"),
+            '|' => Token::Op("|"),
+            '^' => Token::Op("^"),
+            '~' => Token::Op("~"),
+
+            '<' | '>' => {
+                if chars.next().map(|(_, d)| d) != Some(c) {
+                    return Err(syntax_error(&pos,
+                      format!("unexpected character '{}'", c)));
+                }
+                Token::Op(if c == '<' { "<<" } else { ">>" })
+            },
+
+            '\'' => Token::Number(lex_char(&mut chars, &pos)?),
+
+            '"' => Token::Str(lex_string(&mut chars, &pos)?),
+
+            c if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                digits.push(c);
+                while let Some(&(_, d)) = chars.peek() {
+                    if !is_ident_char(d) {
+                        break;
+                    }
+                    digits.push(d);
+                    chars.next();
+                }
+                Token::Number(parse_number(&digits, &pos)?)
+            },
+
+            c if is_ident_char(c) || c == '.' => {
+                let mut ident = String::new();
+                ident.push(c);
+                while let Some(&(_, c)) = chars.peek() {
+                    if !is_ident_char(c) && c != '.' {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                Token::Ident(ident)
+            },
+
+            c => return Err(syntax_error(&pos,
+              format!("unexpected character '{}'", c))),
+        };
+        tokens.push((pos, tok));
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(digits: &str, pos: &SourcePos) -> Result<u16, AsmError> {
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x") | Some("0X") => (16, &digits[2..]),
+        Some("0o") | Some("0O") => (8, &digits[2..]),
+        Some("0b") | Some("0B") => (2, &digits[2..]),
+        _ => (10, digits),
+    };
+    match u32::from_str_radix(digits, radix) {
+        Ok(n) if n <= u16::MAX as u32 => Ok(n as u16),
+        Ok(n) => Err(syntax_error(pos, format!("number out of range ({})", n))),
+        Err(_) => Err(syntax_error(pos, "malformed number".to_string())),
+    }
+}
+
+#[inline]
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// char literals as syndis writes them are a single raw character between
+//   quotes, so '\' and ''' are literal; anything longer is an escape
+fn lex_char(chars: &mut Peekable<CharIndices>, pos: &SourcePos
+  ) -> Result<u16, AsmError> {
+    let (_, c) = chars.next()
+      .ok_or_else(|| syntax_error(pos, "unterminated char literal".to_string()))?;
+
+    // '\'' is an escaped quote, not a backslash followed by junk
+    let escaped_quote = c == '\\' && {
+        let mut ahead = chars.clone();
+        ahead.next().map(|(_, c)| c) == Some('\'')
+          && ahead.next().map(|(_, c)| c) == Some('\'')
+    };
+
+    let c = match (c, chars.peek()) {
+        (_, Some((_, '\''))) if !escaped_quote => c as u32,
+        ('\\', _) => lex_escape(chars, pos)?,
+        _ => return Err(syntax_error(pos,
+          "unterminated char literal".to_string())),
+    };
+
+    match chars.next() {
+        Some((_, '\'')) => { },
+        _ => return Err(syntax_error(pos,
+          "unterminated char literal".to_string())),
+    };
+    char_word(c, pos)
+}
+
+fn lex_string(chars: &mut Peekable<CharIndices>, pos: &SourcePos
+  ) -> Result<Vec<u16>, AsmError> {
+    let mut words = Vec::new();
+    loop {
+        let c = match chars.next() {
+            Some((_, '"')) => return Ok(words),
+            Some((_, '\\')) => lex_escape(chars, pos)?,
+            Some((_, c)) => c as u32,
+            None => return Err(syntax_error(pos,
+              "unterminated string literal".to_string())),
+        };
+        words.push(char_word(c, pos)?);
+    }
+}
+
+// the inverse of asm::escape_string
+fn lex_escape(chars: &mut Peekable<CharIndices>, pos: &SourcePos
+  ) -> Result<u32, AsmError> {
+    let bad_escape = || syntax_error(pos, "invalid escape sequence".to_string());
+    match chars.next().ok_or_else(bad_escape)?.1 {
+        'n' => Ok(b'\n' as u32),
+        't' => Ok(b'\t' as u32),
+        'r' => Ok(b'\r' as u32),
+        '0' => Ok(0),
+        '\\' => Ok(b'\\' as u32),
+        '"' => Ok(b'"' as u32),
+        '\'' => Ok(b'\'' as u32),
+        'u' => {
+            if chars.next().map(|(_, c)| c) != Some('{') {
+                return Err(bad_escape());
+            }
+            let mut hex = String::new();
+            loop {
+                match chars.next().ok_or_else(bad_escape)?.1 {
+                    '}' => break,
+                    c => hex.push(c),
+                };
+            }
+            u32::from_str_radix(&hex, 16).map_err(|_| bad_escape())
+        },
+        _ => Err(bad_escape()),
+    }
+}
+
+#[inline]
+fn char_word(c: u32, pos: &SourcePos) -> Result<u16, AsmError> {
+    if c <= u16::MAX as u32 {
+        Ok(c as u16)
+    } else {
+        Err(syntax_error(pos, format!("character out of range ({})", c)))
+    }
+}
+
+#[inline]
+fn syntax_error(pos: &SourcePos, msg: String) -> AsmError {
+    AsmError::SyntaxError(pos.clone(), msg)
+}
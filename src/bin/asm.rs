@@ -0,0 +1,51 @@
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    fs::File,
+    path::PathBuf,
+    process,
+};
+
+use synacor_vm::{
+    binary,
+    assembler::{assemble, assemble_file},
+};
+
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Options {
+    #[structopt(short, long, parse(from_os_str))]
+    output_file: Option<PathBuf>,
+
+    #[structopt(name="FILE", parse(from_os_str))]
+    input_file: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args();
+
+    let image = if let Some(path) = options.input_file {
+        assemble_file(path)
+    } else {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        assemble(&source)
+    };
+
+    let image = match image {
+        Ok(image) => binary::write_binary(&image),
+        Err(e) => {
+            eprintln!("synasm: {}", e);
+            process::exit(1);
+        },
+    };
+
+    if let Some(path) = options.output_file {
+        File::create(path)?.write_all(&image)?;
+    } else {
+        io::stdout().write_all(&image)?;
+    }
+
+    Ok(())
+}
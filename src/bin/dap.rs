@@ -0,0 +1,41 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::PathBuf,
+};
+
+use synacor_vm::{
+    binary,
+    dap::DapServer,
+    vm::Vm,
+};
+
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Options {
+    // an image for `attach` requests; `launch` requests name their own
+    #[structopt(name="FILE", parse(from_os_str))]
+    image_file: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args();
+
+    let image = match options.image_file {
+        Some(path) => {
+            let mut prog = Vec::new();
+            File::open(path)?.read_to_end(&mut prog)?;
+            let mut vm = Vm::new();
+            vm.load(&binary::read_binary(&prog)?)?;
+            Some(vm)
+        },
+        None => None,
+    };
+
+    // the protocol has stdin and stdout to itself
+    let mut server = DapServer::new(image);
+    server.serve(BufReader::new(io::stdin()), &mut io::stdout())?;
+    Ok(())
+}
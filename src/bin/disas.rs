@@ -1,13 +1,23 @@
 use std::{
     error::Error,
-    io::{self, BufReader, Read},
+    io::{self, BufReader, Read, Write},
     fs::File,
     path::PathBuf,
+    process,
 };
 
 use synacor_vm::{
     binary,
-    asm::{ImageMap, DisAsmOpts, read_labels},
+    assembler::assemble,
+    vm::Vm,
+    unpack::{self, StopEvent},
+    map::ProjectMap,
+    asm::{
+        ImageMap,
+        DisAsmOpts,
+        DisAsmStyle,
+        DEFAULT_STRING_THRESHOLD,
+    },
 };
 
 use structopt::StructOpt;
@@ -17,15 +27,55 @@ struct Options {
     #[structopt(short, long)]
     autolabel: bool,
 
-    #[structopt(short, long)]
+    #[structopt(short, long, conflicts_with="round-trip")]
     line_addrs: bool,
 
+    #[structopt(short, long, possible_values=&["text", "json", "html"],
+      conflicts_with="round-trip")]
+    format: Option<String>,
+
+    #[structopt(long)]
+    style: Option<DisAsmStyle>,
+
+    #[structopt(short, long)]
+    round_trip: bool,
+
+    #[structopt(short, long)]
+    strings: bool,
+
+    #[structopt(short, long)]
+    xrefs: bool,
+
+    #[structopt(long)]
+    fold_out: bool,
+
+    #[structopt(long)]
+    string_threshold: Option<f32>,
+
+    #[structopt(short, long)]
+    unpack: bool,
+
+    #[structopt(long)]
+    until_input: bool,
+
+    #[structopt(long)]
+    until_addr: Option<usize>,
+
+    #[structopt(long)]
+    until_steps: Option<usize>,
+
+    #[structopt(long, parse(from_os_str))]
+    unpack_input: Option<PathBuf>,
+
     #[structopt(short, long, parse(from_os_str))]
     output_file: Option<PathBuf>,
 
     #[structopt(short, long, parse(from_os_str))]
     map_file: Option<PathBuf>,
 
+    #[structopt(long, parse(from_os_str))]
+    save_map: Option<PathBuf>,
+
     #[structopt(name="FILE", parse(from_os_str))]
     input_file: Option<PathBuf>,
 }
@@ -33,6 +83,9 @@ struct Options {
 fn main() -> Result<(), Box<dyn Error>> {
     let options = Options::from_args();
 
+    let title = options.input_file.as_ref()
+      .map_or_else(|| "stdin".to_string(), |path| path.display().to_string());
+
     let prog = {
         let mut prog = Vec::new();
         if let Some(path) = options.input_file {
@@ -43,29 +96,127 @@ fn main() -> Result<(), Box<dyn Error>> {
         binary::read_binary(&prog)?
     };
 
-    let initial_labels = {
+    let (initial_labels, project) = {
         if let Some(path) = options.map_file {
             let map_file = File::open(path)?;
-            let mut map_file = BufReader::new(map_file);
-            Some(read_labels(&mut map_file)?)
+            let project = ProjectMap::read(&mut BufReader::new(map_file))?;
+            (Some(project.labels.clone()), project)
         } else {
-            None
+            (None, ProjectMap::new())
+        }
+    };
+
+    let mut output: Box<dyn Write> = if let Some(path) = options.output_file {
+        Box::new(File::create(path)?)
+    } else {
+        Box::new(io::stdout())
+    };
+
+    let (prog, highlights) = if options.unpack {
+        let mut events = Vec::new();
+        if options.until_input {
+            events.push(StopEvent::Input);
+        }
+        if let Some(ip) = options.until_addr {
+            events.push(StopEvent::Address(ip));
+        }
+        if let Some(n) = options.until_steps {
+            events.push(StopEvent::Steps(n));
+        }
+        if events.is_empty() {
+            events.push(StopEvent::Input);
+        }
+
+        let mut input = Vec::new();
+        if let Some(path) = options.unpack_input {
+            File::open(path)?.read_to_end(&mut input)?;
+        }
+
+        let mut vm = Vm::new();
+        vm.load(&prog)?;
+        let unpacked = unpack::run_until(vm, &events, &input)?;
+        let regions = unpack::diff_regions(&prog, unpacked.vm.memory());
+
+        writeln!(output, "; unpacked: {} after {} instructions",
+          unpacked.reason, unpacked.steps)?;
+        for r in &regions {
+            writeln!(output, "; modified at runtime: {}..{} ({} words)",
+              r.start, r.end, r.len())?;
         }
+
+        let end = regions.last().map_or(prog.len(), |r| r.end.max(prog.len()));
+        (unpacked.vm.memory()[..end].to_vec(), regions)
+    } else {
+        (prog, Vec::new())
     };
 
     let opts = DisAsmOpts {
         autolabel: options.autolabel,
         line_addrs: options.line_addrs,
         initial_labels,
+        strings: options.strings,
+        string_threshold: options.string_threshold
+          .unwrap_or(DEFAULT_STRING_THRESHOLD),
+        string_hints: project.string_hints.clone(),
+        highlights,
+        round_trip: options.round_trip,
+        xrefs: options.xrefs,
+        comments: project.comments.clone(),
+        signatures: project.signatures.clone(),
+        regions: project.regions.clone(),
+        entries: project.entries.clone(),
+        style: options.style.unwrap_or_default(),
+        fold_out: options.fold_out,
     };
 
     let map = ImageMap::new(&prog, &opts);
 
-    if let Some(path) = options.output_file {
-        map.disasm(&mut File::create(path)?, &opts)?;
-    } else {
-        map.disasm(&mut io::stdout(), &opts)?;
+    if let Some(path) = options.save_map {
+        let mut learned = project;
+        learned.learn(&map);
+        learned.write(&mut File::create(path)?)?;
+    }
+
+    match options.format.as_deref().unwrap_or("text") {
+        "json" => {
+            map.write_json(&mut output, &opts)?;
+            return Ok(());
+        },
+
+        "html" => {
+            map.write_html(&mut output, &title, &opts)?;
+            return Ok(());
+        },
+
+        _ if !options.round_trip => {
+            map.disasm(&mut output, &opts)?;
+            return Ok(());
+        },
+
+        _ => { },
+    };
+
+    let mut text = Vec::new();
+    map.disasm(&mut text, &opts)?;
+    output.write_all(&text)?;
+    output.flush()?;
+
+    let image = match assemble(&String::from_utf8(text)?) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("round-trip check failed: {}", e);
+            process::exit(1);
+        },
+    };
+
+    let mismatch = (0..prog.len().max(image.len()))
+      .find(|&i| prog.get(i) != image.get(i));
+    if let Some(i) = mismatch {
+        eprintln!("round-trip check failed at address {}: {:?} became {:?}",
+          i, prog.get(i), image.get(i));
+        process::exit(1);
     }
+    eprintln!("round-trip check passed ({} words)", image.len());
 
     Ok(())
 }
@@ -0,0 +1,67 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Read},
+    net::TcpListener,
+    path::PathBuf,
+};
+
+use synacor_vm::{
+    binary,
+    gdb::{GdbServer, Stdio},
+    vm::Vm,
+};
+
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Options {
+    #[structopt(name="FILE", parse(from_os_str))]
+    image_file: PathBuf,
+
+    #[structopt(short, long, parse(from_os_str))]
+    initial_input: Option<PathBuf>,
+
+    #[structopt(short, long, default_value="1234")]
+    port: u16,
+
+    // speak the protocol on stdin and stdout, rather than listening; the
+    //   program's output goes to the debugger's console, and its input is
+    //   only what's given with -i
+    #[structopt(long)]
+    stdio: bool,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::from_args();
+
+    let prog = {
+        let mut prog = Vec::new();
+        File::open(options.image_file)?.read_to_end(&mut prog)?;
+        binary::read_binary(&prog)?
+    };
+
+    let mut vm = Vm::new();
+    vm.load(&prog)?;
+    let mut server = GdbServer::new(vm);
+
+    let mut input: Box<dyn Read> = match options.initial_input {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::empty()),
+    };
+
+    if options.stdio {
+        server.serve_console(&mut Stdio::new(), &mut input)?;
+    } else {
+        let listener = TcpListener::bind(("127.0.0.1", options.port))?;
+        eprintln!("listening on {}", listener.local_addr()?);
+        let (mut conn, peer) = listener.accept()?;
+        eprintln!("debugger connected from {}", peer);
+        // acks and replies are tiny, and shouldn't wait on each other
+        conn.set_nodelay(true)?;
+        let mut input = input.chain(io::stdin());
+        server.serve(&mut conn, &mut input, &mut io::stdout())?;
+    }
+
+    Ok(())
+}
@@ -1,27 +1,46 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
+    env,
     error::Error,
     fmt,
     fs::File,
-    io::{self, BufReader, BufRead, Cursor, Read, Write},
-    path::PathBuf,
+    io::{self, BufReader, BufRead, Cursor, ErrorKind, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
     sync::{Arc, atomic::{AtomicBool, Ordering}},
 };
 
 use synacor_vm::{
     binary,
-    vm::{self, Vm, VmState, Instruction},
+    vm::{self, Frame, Vm, VmState, Instruction},
+    map::ProjectMap,
+    expr::{Expr, ExprError},
     asm::{
         AsmError,
+        AsmItem,
         DisAsm,
         DisAsmOpts,
         DisAsmError,
+        DisAsmStyle,
         ImageMap,
         Labels,
-        read_labels,
+        escape_string,
     },
 };
 
+use rustyline::{
+    Context,
+    Editor,
+    Helper,
+    completion::Completer,
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+};
+
+use serde_json::{json, Value};
+
 use structopt::StructOpt;
 
 #[cfg(windows)]
@@ -33,11 +52,11 @@ use winapi::um::{
     consoleapi::{GetConsoleMode, SetConsoleMode},
 };
 
-const BEGIN_RED: &'static str = "\u{1b}[1;31m";
-const BEGIN_GREEN: &'static str = "\u{1b}[1;32m";
-const BEGIN_BLUE: &'static str = "\u{1b}[1;34m";
-const BEGIN_YELLOW: &'static str = "\u{1b}[1;33m";
-const CLEAR_COLOR: &'static str = "\u{1b}[0m";
+const BEGIN_RED: &str = "\u{1b}[1;31m";
+const BEGIN_GREEN: &str = "\u{1b}[1;32m";
+const BEGIN_BLUE: &str = "\u{1b}[1;34m";
+const BEGIN_YELLOW: &str = "\u{1b}[1;33m";
+const CLEAR_COLOR: &str = "\u{1b}[0m";
 
 #[derive(StructOpt, Debug)]
 struct Options {
@@ -52,17 +71,55 @@ struct Options {
 
     #[structopt(short, long, parse(from_os_str))]
     map_file: Option<PathBuf>,
+
+    #[structopt(long)]
+    no_autosave: bool,
+
+    #[structopt(long)]
+    style: Option<DisAsmStyle>,
+
+    #[structopt(short, long, parse(from_os_str))]
+    script: Option<PathBuf>,
+
+    // quit once the script has run, rather than prompting
+    #[structopt(short, long)]
+    batch: bool,
+
+    // defaults to ~/.syntrace_history
+    #[structopt(long, parse(from_os_str))]
+    history: Option<PathBuf>,
 }
 
+// how deep aliases, definitions and sourced files may nest
+const MAX_NESTING: usize = 16;
+
+// for completion; the long forms of what `parse_command` takes
+const COMMANDS: &[&str] = &[
+    "step", "next", "finish", "until", "continue", "label", "unlabel",
+    "clear", "break", "tbreak", "delete", "condition", "ignore", "enable",
+    "disable", "info", "backtrace", "push", "pop", "poke", "examine",
+    "stack", "disassemble", "set", "status", "remap", "xrefs", "save",
+    "load", "source", "alias", "define", "unalias", "watch", "session",
+    "help", "quit",
+];
+
+const REGISTERS: &[&str] = &["r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7"];
+
 #[derive(Debug)]
 pub enum TracerError {
     VmError(vm::Error),
     IOError(io::Error),
     AsmError(AsmError),
     DisAsmError(DisAsmError),
+    ExprError(ExprError),
     UnknownCommand(String),
     UnknownLabel(String),
     UnknownRegister(String),
+    NoMapFile,
+    UnknownBreakpoint(usize),
+    UnknownAlias(String),
+    ReadlineError(ReadlineError),
+    BadSession(String),
 }
 
 impl From<vm::Error> for TracerError {
@@ -89,6 +146,18 @@ impl From<DisAsmError> for TracerError {
     }
 }
 
+impl From<ExprError> for TracerError {
+    fn from(other: ExprError) -> Self {
+        TracerError::ExprError(other)
+    }
+}
+
+impl From<ReadlineError> for TracerError {
+    fn from(other: ReadlineError) -> Self {
+        TracerError::ReadlineError(other)
+    }
+}
+
 impl fmt::Display for TracerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -97,69 +166,326 @@ impl fmt::Display for TracerError {
             TracerError::AsmError(e) => write!(f, "assembly error: {}", e),
             TracerError::DisAsmError(e) =>
               write!(f, "disassembly error: {}", e),
+            TracerError::ExprError(e) => write!(f, "expression error: {}", e),
             TracerError::UnknownCommand(line) =>
               write!(f, "unknown command: \"{}\"", line),
             TracerError::UnknownLabel(lbl) =>
               write!(f, "unknown label: \"{}\"", lbl),
             TracerError::UnknownRegister(reg) =>
               write!(f, "unknown register: \"{}\"", reg),
+            TracerError::NoMapFile =>
+              write!(f, "no map file loaded, give a path"),
+            TracerError::UnknownBreakpoint(id) =>
+              write!(f, "no breakpoint number {}", id),
+            TracerError::UnknownAlias(name) =>
+              write!(f, "no alias or definition \"{}\"", name),
+            TracerError::ReadlineError(e) =>
+              write!(f, "line editing error: {}", e),
+            TracerError::BadSession(msg) =>
+              write!(f, "bad session file: {}", msg),
         }
     }
 }
 
 impl Error for TracerError { }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExamineFormat {
+    Hex,
+    Decimal,
+    Char,
+    // length-prefixed strings, rather than words
+    String,
+}
+
+// completes the first word of a command as a command, and the rest as
+//   labels or registers; the word lists are refreshed before each prompt
+#[derive(Default)]
+struct TracerHelper {
+    commands: Vec<String>,
+    symbols: Vec<String>,
+}
+
+impl Completer for TracerHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>
+      ) -> rustyline::Result<(usize, Vec<String>)> {
+        let is_separator = |c: char|
+          c.is_whitespace() || "[]()+-*/%&|^!~=<>;,".contains(c);
+        let start = line[..pos].rfind(is_separator).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        let before = line[..start].rsplit(';').next().unwrap_or("");
+        let words = if before.trim().is_empty() {
+            &self.commands
+        } else {
+            &self.symbols
+        };
+
+        let mut candidates: Vec<_> = words.iter()
+          .filter(|w| w.starts_with(word))
+          .cloned()
+          .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for TracerHelper {
+    type Hint = String;
+}
+
+impl Highlighter for TracerHelper { }
+
+impl Validator for TracerHelper { }
+
+impl Helper for TracerHelper { }
+
+// a condition, with the text it was parsed from
+pub type Condition = (String, Expr);
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    id: usize,
+    addr: usize,
+    condition: Option<Condition>,
+    enabled: bool,
+    // deleted the first time it stops execution
+    temporary: bool,
+    hits: usize,
+    // how many more hits to pass over before stopping
+    ignore: usize,
+}
+
+// stops when the word at `addr` changes from `value`
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    id: usize,
+    addr: usize,
+    value: u16,
+}
+
 pub struct Tracer {
     vm: Vm,
     labels: Labels,
+    // the map as last loaded or saved, including what we don't edit here
+    project: ProjectMap,
+    map_path: Option<PathBuf>,
+    autosave: bool,
     in_cursor: Cursor<Vec<u8>>,
     out_buf: Vec<u8>,
-    breakpoints: HashSet<usize>,
+    // every line of output so far
+    transcript: Vec<u8>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_breakpoint: usize,
     map: ImageMap,
     autolabel: bool,
+    style: DisAsmStyle,
+    // a word standing for the start of a command
+    aliases: HashMap<String, String>,
+    // a word standing for a `;`-separated list of commands
+    definitions: HashMap<String, String>,
+    nesting: usize,
+    // only when reading commands from a terminal
+    editor: Option<Editor<TracerHelper, DefaultHistory>>,
+    history_path: Option<PathBuf>,
     interrupt: Arc<AtomicBool>,
 }
 
 impl Tracer {
-    pub fn new(vm: Vm, labels: Option<Labels>, initial_input: Option<Vec<u8>>,
-      autolabel: bool) -> Self {
-        let map = ImageMap::new(vm.memory(), &DisAsmOpts {
-            autolabel,
-            line_addrs: false,
-            initial_labels: labels.clone(),
-        });
+    pub fn new(vm: Vm, map_path: Option<PathBuf>, project: ProjectMap,
+      initial_input: Option<Vec<u8>>, autolabel: bool, autosave: bool,
+      style: DisAsmStyle) -> Self {
+        let map = ImageMap::new(vm.memory(),
+          &Self::map_opts(&project, &project.labels, autolabel));
 
         Self {
             vm,
-            labels: labels.unwrap_or_else(|| HashMap::new()),
-            in_cursor: Cursor::new(initial_input.unwrap_or_else(|| Vec::new())),
+            labels: project.labels.clone(),
+            project,
+            map_path,
+            autosave,
+            in_cursor: Cursor::new(initial_input.unwrap_or_default()),
             out_buf: Vec::new(),
-            breakpoints: HashSet::new(),
+            transcript: Vec::new(),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_breakpoint: 1,
             map,
             autolabel,
+            style,
+            aliases: HashMap::new(),
+            definitions: HashMap::new(),
+            nesting: 0,
+            editor: None,
+            history_path: None,
             interrupt: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub fn run(&mut self) -> Result<(), TracerError> {
-        loop {
+    pub fn run(&mut self, script: Option<&Path>, batch: bool
+      ) -> Result<(), TracerError> {
+        let mut state = match script {
+            Some(path) => self.source(path)?,
+            None => TracerState::WaitCommand,
+        };
+        // a batch run always ends here; without a map file, there's
+        //   nowhere for its labels to go, and nobody to ask
+        if batch {
+            if self.autosave && self.is_dirty() {
+                match self.map_path {
+                    Some(_) => self.save(None)?,
+                    None => println!("{}labels not saved: {}{}",
+                      BEGIN_YELLOW, TracerError::NoMapFile, CLEAR_COLOR),
+                };
+            }
+            state = TracerState::Quit;
+        }
+
+        while let TracerState::WaitCommand = state {
             self.status_line();
-            let cmd = self.get_command();
-            match cmd {
-                Ok(cmd) => {
-                    match self.do_cmd(cmd)? {
-                        TracerState::WaitCommand => { },
-
-                        TracerState::Quit => {
-                            println!("{}bye!{}", BEGIN_YELLOW, CLEAR_COLOR);
-                            return Ok(());
-                        },
-                    };
+            // end of input quits, the same as typing it
+            let line = self.get_command()?
+              .unwrap_or_else(|| "quit".to_string());
+            state = self.run_line(&line)?.unwrap_or(TracerState::WaitCommand);
+        }
+        self.save_history();
+        println!("{}bye!{}", BEGIN_YELLOW, CLEAR_COLOR);
+        Ok(())
+    }
+
+    // runs each `;`-separated command in turn, stopping at the first one
+    //   that can't be parsed, in which case the result is `None`
+    fn run_line(&mut self, line: &str
+      ) -> Result<Option<TracerState>, TracerError> {
+        let line = line.trim();
+        // the rest of these lines are kept whole, `;`s and all
+        let cmds: Vec<&str> = match line.split_whitespace().next() {
+            Some("alias") | Some("define") | None => vec![line],
+            _ => line.split(';').map(str::trim)
+              .filter(|cmd| !cmd.is_empty())
+              .collect(),
+        };
+
+        for cmd in cmds {
+            let cmd = self.expand_alias(cmd);
+            let word = cmd.split_whitespace().next().unwrap_or("");
+            let state = match self.definitions.get(word).cloned() {
+                Some(body) => match self.nested(|t| t.run_line(&body))? {
+                    Some(state) => state,
+                    None => return Ok(None),
                 },
 
-                Err(e) => {
-                    println!("{}{}{}", BEGIN_RED, e, CLEAR_COLOR);
+                None => match self.parse_command(&cmd) {
+                    Ok(cmd) => self.do_cmd(cmd)?,
+                    Err(e) => {
+                        println!("{}{}{}", BEGIN_RED, e, CLEAR_COLOR);
+                        return Ok(None);
+                    },
+                },
+            };
+            if let TracerState::Quit = state {
+                return Ok(Some(state));
+            }
+        }
+        Ok(Some(TracerState::WaitCommand))
+    }
+
+    fn expand_alias(&self, cmd: &str) -> String {
+        let word = cmd.split_whitespace().next().unwrap_or("");
+        match self.aliases.get(word) {
+            Some(text) => format!("{} {}", text, rest_of(cmd, 1))
+              .trim().to_string(),
+            None => cmd.to_string(),
+        }
+    }
+
+    // guards against definitions and scripts which run themselves
+    fn nested<F: FnOnce(&mut Self) -> Result<Option<TracerState>, TracerError>>(
+      &mut self, f: F) -> Result<Option<TracerState>, TracerError> {
+        if self.nesting >= MAX_NESTING {
+            println!("{}commands nested too deeply{}", BEGIN_RED, CLEAR_COLOR);
+            return Ok(None);
+        }
+        self.nesting += 1;
+        let res = f(self);
+        self.nesting -= 1;
+        res
+    }
+
+    // a script stops at its first bad command, like a definition does
+    fn source(&mut self, path: &Path) -> Result<TracerState, TracerError> {
+        let lines = match File::open(path) {
+            Ok(file) => BufReader::new(file).lines()
+              .collect::<Result<Vec<_>, _>>()?,
+            Err(e) => {
+                println!("{}{}: {}{}", BEGIN_RED, path.display(), e, CLEAR_COLOR);
+                return Ok(TracerState::WaitCommand);
+            },
+        };
+
+        for (n, line) in lines.iter().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match self.nested(|t| t.run_line(line))? {
+                Some(TracerState::Quit) => return Ok(TracerState::Quit),
+                Some(TracerState::WaitCommand) => { },
+                None => {
+                    println!("{}{} line {}: script stopped{}",
+                      BEGIN_RED, path.display(), n + 1, CLEAR_COLOR);
+                    break;
                 },
+            };
+        }
+        Ok(TracerState::WaitCommand)
+    }
+
+    fn list_aliases(&self) {
+        let mut aliases: Vec<_> = self.aliases.iter()
+          .map(|(name, text)| ("alias", name, text))
+          .chain(self.definitions.iter()
+            .map(|(name, body)| ("define", name, body)))
+          .collect();
+        aliases.sort_by_key(|(_, name, _)| name.as_str());
+        if aliases.is_empty() {
+            println!("{}no aliases{}", BEGIN_YELLOW, CLEAR_COLOR);
+        }
+        for (kind, name, text) in aliases {
+            println!("{} {} {}", kind, name, text);
+        }
+    }
+
+    pub fn init_editor(&mut self, history_path: Option<PathBuf>
+      ) -> Result<(), TracerError> {
+        if !io::stdin().is_terminal() {
+            return Ok(());
+        }
+
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(TracerHelper::default()));
+        if let Some(path) = &history_path {
+            match editor.load_history(path) {
+                Ok(_) => { },
+                Err(ReadlineError::Io(e)) if e.kind() == ErrorKind::NotFound =>
+                  { },
+                Err(e) => return Err(e.into()),
+            };
+        }
+        self.editor = Some(editor);
+        self.history_path = history_path;
+        Ok(())
+    }
+
+    fn save_history(&mut self) {
+        if let (Some(editor), Some(path)) =
+          (&mut self.editor, &self.history_path) {
+            if let Err(e) = editor.save_history(path) {
+                println!("{}can't save history: {}{}",
+                  BEGIN_RED, e, CLEAR_COLOR);
             }
         }
     }
@@ -185,7 +511,9 @@ impl Tracer {
 
         match self.vm.decode_next() {
             Ok((_, instr)) => {
-                match instr.disasm(self.vm.ip(), &self.map, &mut io::stdout()) {
+                match instr.disasm(self.vm.ip(), &self.map,
+                  &DisAsmOpts { style: self.style, ..DisAsmOpts::default() },
+                  &mut io::stdout()) {
                     Ok(_) => { },
                     Err(e) => println!("{}disassembly error: {}{}",
                       BEGIN_RED, e, CLEAR_COLOR),
@@ -197,12 +525,82 @@ impl Tracer {
         }
     }
 
-    fn remap(&mut self) {
-        self.map = ImageMap::new(self.vm.memory(), &DisAsmOpts {
-            autolabel: self.autolabel,
+    fn map_opts(project: &ProjectMap, labels: &Labels, autolabel: bool
+      ) -> DisAsmOpts {
+        DisAsmOpts {
+            autolabel,
             line_addrs: false,
-            initial_labels: Some(self.labels.clone()),
-        });
+            initial_labels: Some(labels.clone()),
+            string_hints: project.string_hints.clone(),
+            regions: project.regions.clone(),
+            entries: project.entries.clone(),
+            ..DisAsmOpts::default()
+        }
+    }
+
+    fn remap(&mut self) {
+        self.map = ImageMap::new(self.vm.memory(),
+          &Self::map_opts(&self.project, &self.labels, self.autolabel));
+    }
+
+    #[inline]
+    fn is_dirty(&self) -> bool {
+        self.labels != self.project.labels
+    }
+
+    fn save(&mut self, path: Option<PathBuf>) -> Result<(), TracerError> {
+        let path = path.or_else(|| self.map_path.clone())
+          .ok_or(TracerError::NoMapFile)?;
+        let mut ours = self.project.clone();
+        ours.labels = self.labels.clone();
+
+        // the file may have been edited (or saved by another tracer) since
+        //   we loaded it, so fold in their changes rather than clobber them
+        let merged = match read_project(&path) {
+            Ok(theirs) if Some(&path) == self.map_path.as_ref()
+              && theirs != self.project => {
+                let (merged, conflicts) =
+                  ProjectMap::merge(&self.project, &ours, &theirs);
+                println!("{}{} changed on disk, merged ({} conflicts){}",
+                  BEGIN_YELLOW, path.display(), conflicts.len(), CLEAR_COLOR);
+                for conflict in &conflicts {
+                    println!("{}{}{}", BEGIN_RED, conflict, CLEAR_COLOR);
+                }
+                merged
+            },
+
+            Ok(_) => ours,
+            Err(AsmError::IOError(e)) if e.kind() == ErrorKind::NotFound =>
+              ours,
+            Err(e) => return Err(e.into()),
+        };
+
+        merged.write(&mut File::create(&path)?)?;
+        println!("{}saved {} labels to {}{}",
+          BEGIN_YELLOW, merged.labels.len(), path.display(), CLEAR_COLOR);
+
+        self.labels = merged.labels.clone();
+        self.project = merged;
+        self.map_path = Some(path);
+        self.remap();
+        Ok(())
+    }
+
+    fn load(&mut self, path: Option<PathBuf>) -> Result<(), TracerError> {
+        let path = path.or_else(|| self.map_path.clone())
+          .ok_or(TracerError::NoMapFile)?;
+        let project = read_project(&path)?;
+        if self.is_dirty() {
+            println!("{}discarding unsaved labels{}", BEGIN_YELLOW, CLEAR_COLOR);
+        }
+        println!("{}loaded {} labels from {}{}",
+          BEGIN_YELLOW, project.labels.len(), path.display(), CLEAR_COLOR);
+
+        self.labels = project.labels.clone();
+        self.project = project;
+        self.map_path = Some(path);
+        self.remap();
+        Ok(())
     }
 
     fn ensure_input(&mut self, single_step: bool) -> Result<bool, TracerError> {
@@ -223,26 +621,21 @@ impl Tracer {
     }
 
     fn pump_output(&mut self) -> Result<(), TracerError> {
-        match self.out_buf.last() {
-            Some(b'\n') =>  {
-                print!("{}output> {}", BEGIN_GREEN, CLEAR_COLOR);
-                io::stdout().write_all(&mut self.out_buf)?;
-                self.out_buf.clear();
-            },
-            _ => { },
-        };
+        if let Some(b'\n') = self.out_buf.last() {
+            print!("{}output> {}", BEGIN_GREEN, CLEAR_COLOR);
+            io::stdout().write_all(&self.out_buf)?;
+            self.transcript.append(&mut self.out_buf);
+        }
         Ok(())
     }
 
     fn step(&mut self, single_step: bool) -> Result<VmState, TracerError> {
         let (_, instr) = self.vm.decode_next()?;
         match instr {
-            Instruction::In(_) => {
-                if !self.ensure_input(single_step)? {
-                    // an interrupt happened, don't step
-                    self.interrupt.store(true, Ordering::Relaxed);
-                    return Ok(VmState::Running)
-                }
+            Instruction::In(_) if !self.ensure_input(single_step)? => {
+                // an interrupt happened, don't step
+                self.interrupt.store(true, Ordering::Relaxed);
+                return Ok(VmState::Running)
             },
 
             Instruction::Halt => {
@@ -257,36 +650,494 @@ impl Tracer {
 
         let state = self.vm.step(&mut self.in_cursor, &mut self.out_buf)?;
         self.pump_output()?;
+        if let Some(mismatch) = self.vm.call_mismatch() {
+            println!("{}call stack mismatch: {}{}",
+              BEGIN_RED, mismatch, CLEAR_COLOR);
+        }
         Ok(state)
     }
 
+    // step until `done`, a breakpoint, a halt or an interrupt
+    fn run_until<F: Fn(&Self) -> bool>(&mut self, done: F
+      ) -> Result<(), TracerError> {
+        loop {
+            if self.interrupt.swap(false, Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            if let VmState::Halted = self.step(false)? {
+                return Ok(());
+            }
+
+            let watched = self.check_watchpoints();
+            if self.check_breakpoints() || watched || done(self) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn breakpoint_mut(&mut self, id: usize
+      ) -> Result<&mut Breakpoint, TracerError> {
+        self.breakpoints.iter_mut()
+          .find(|bp| bp.id == id)
+          .ok_or(TracerError::UnknownBreakpoint(id))
+    }
+
+    // should we stop at the current ip? a condition which can't be
+    //   evaluated stops us too, so it can be fixed
+    fn check_breakpoints(&mut self) -> bool {
+        let ip = self.vm.ip();
+        let mut stopped = Vec::new();
+        for bp in self.breakpoints.iter_mut()
+              .filter(|bp| bp.enabled && bp.addr == ip) {
+            if let Some((source, cond)) = &bp.condition {
+                match cond.is_true(&self.vm) {
+                    Ok(true) => { },
+                    Ok(false) => continue,
+                    Err(e) => {
+                        println!("{}breakpoint {}: can't evaluate \"{}\": {}{}",
+                          BEGIN_RED, bp.id, source, e, CLEAR_COLOR);
+                        stopped.push(bp.id);
+                        continue;
+                    },
+                };
+            }
+
+            bp.hits += 1;
+            if bp.ignore > 0 {
+                bp.ignore -= 1;
+                continue;
+            }
+
+            println!("{}breakpoint {} at {}{}", BEGIN_YELLOW, bp.id,
+              self.map.describe_addr(ip), CLEAR_COLOR);
+            stopped.push(bp.id);
+        }
+
+        self.breakpoints.retain(|bp| !(bp.temporary && stopped.contains(&bp.id)));
+        !stopped.is_empty()
+    }
+
+    fn backtrace(&self) {
+        println!("#0  {:<24} stack# {}",
+          self.map.describe_addr(self.vm.ip()), self.vm.stack().len());
+        for (i, frame) in self.vm.frames().iter().rev().enumerate() {
+            println!("#{:<2} {:<24} stack# {:<6} call {}", i + 1,
+              self.map.describe_addr(frame.call_site), frame.stack_depth,
+              self.map.describe_addr(frame.target));
+        }
+    }
+
+    fn examine(&self, addr: usize, count: usize, format: ExamineFormat) {
+        let memory = self.vm.memory();
+        if format == ExamineFormat::String {
+            let mut at = addr;
+            for _ in 0..count {
+                let chars = memory.get(at)
+                  .and_then(|&len| memory.get(at + 1..at + 1 + len as usize));
+                let chars = match chars {
+                    Some(chars) => chars,
+                    None => {
+                        println!("{}invalid address{}", BEGIN_RED, CLEAR_COLOR);
+                        return;
+                    },
+                };
+                println!("{:<24} \"{}\"",
+                  self.map.describe_addr(at), escape_string(chars));
+                at += 1 + chars.len();
+            }
+            return;
+        }
+
+        let end = memory.len().min(addr.saturating_add(count));
+        if addr >= end {
+            println!("{}invalid address{}", BEGIN_RED, CLEAR_COLOR);
+            return;
+        }
+        for row in (addr..end).step_by(8) {
+            let words: Vec<_> = memory[row..end.min(row + 8)].iter()
+              .map(|&w| match format {
+                  ExamineFormat::Hex => format!("{:04x}", w),
+                  // control characters other than the usual escapes stay
+                  //   numbers, to keep the columns lined up
+                  ExamineFormat::Char if escape_string(&[w]).len() <= 2 =>
+                    format!("{:>6}", format!("'{}'", escape_string(&[w]))),
+                  _ => format!("{:6}", w),
+              })
+              .collect();
+            println!("{:<24} {}", self.map.describe_addr(row), words.join(" "));
+        }
+    }
+
+    // where to start disassembling so that a few instructions lead up to
+    //   ip; it's only a guess when the map doesn't line up with memory
+    fn context_start(&self, before: usize) -> usize {
+        let ip = self.vm.ip();
+        let starts: Vec<_> = self.map.stmts.iter()
+          .map(|(addr, _)| *addr)
+          .filter(|&addr| addr < ip)
+          .collect();
+        starts.iter().rev().take(before).rev()
+          .copied()
+          .find(|&start| {
+              let mut addr = start;
+              while addr < ip {
+                  match self.vm.decode(addr) {
+                      Ok((next, _)) => addr = next,
+                      Err(_) => return false,
+                  };
+              }
+              addr == ip
+          })
+          .unwrap_or(ip)
+    }
+
+    // from live memory, with a fresh map for the current labels
+    fn disassemble(&self, start: Option<usize>, count: usize
+      ) -> Result<(), TracerError> {
+        let opts = DisAsmOpts {
+            style: self.style,
+            ..Self::map_opts(&self.project, &self.labels, self.autolabel)
+        };
+        let map = ImageMap::new(self.vm.memory(), &opts);
+        let stdout = io::stdout();
+        let mut w = stdout.lock();
+
+        let mut addr = start.unwrap_or_else(|| self.context_start(3));
+        for _ in 0..count {
+            if addr >= self.vm.memory().len() {
+                break;
+            }
+            let (next, stmt) = match self.vm.decode(addr) {
+                Ok((next, instr)) => (next, AsmItem::Instruction(instr)),
+                Err(_) => (addr + 1, AsmItem::Value(self.vm.memory()[addr])),
+            };
+
+            let bp = self.breakpoints.iter()
+              .filter(|bp| bp.addr == addr)
+              .map(|bp| bp.enabled)
+              .max();
+            write!(w, "{}{}{}{:5}  ",
+              if addr == self.vm.ip() { BEGIN_YELLOW } else { "" },
+              if addr == self.vm.ip() { "=>" } else { "  " },
+              match bp {
+                  Some(true) => "*",
+                  Some(false) => "o",
+                  None => " ",
+              },
+              addr)?;
+            stmt.disasm(addr, &map, &opts, &mut w)?;
+            write!(w, "{}", CLEAR_COLOR)?;
+            addr = next;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    // return addresses are the ones just after a call, and we can tell
+    //   which frame pushed those the shadow call stack knows about
+    fn stack(&self) {
+        let stack = self.vm.stack();
+        if stack.is_empty() {
+            println!("{}empty stack{}", BEGIN_RED, CLEAR_COLOR);
+            return;
+        }
+
+        for (depth, &val) in stack.iter().enumerate().rev() {
+            let addr = val as usize;
+            let after_call = addr.checked_sub(2)
+              .and_then(|at| self.vm.decode(at).ok())
+              .is_some_and(|(next, instr)|
+                next == addr && matches!(instr, Instruction::Call(_)));
+            let frame = self.vm.frames().iter()
+              .position(|f| f.stack_depth == depth && f.return_addr == addr);
+
+            print!("{:>5}  {:5}", depth, val);
+            match frame {
+                Some(i) => print!("  ; ret to {} (frame #{})",
+                  self.map.describe_addr(addr), self.vm.frames().len() - i),
+                None if after_call => print!("  ; ret to {}?",
+                  self.map.describe_addr(addr)),
+                None => { },
+            };
+            println!();
+        }
+    }
+
+    fn check_watchpoints(&mut self) -> bool {
+        let mut stopped = false;
+        for wp in &mut self.watchpoints {
+            let value = self.vm.memory()[wp.addr];
+            if value != wp.value {
+                println!("{}watchpoint {} at {}: {} -> {}{}", BEGIN_YELLOW,
+                  wp.id, self.map.describe_addr(wp.addr), wp.value, value,
+                  CLEAR_COLOR);
+                wp.value = value;
+                stopped = true;
+            }
+        }
+        stopped
+    }
+
+    fn info_breakpoints(&self) {
+        if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+            println!("{}no breakpoints{}", BEGIN_YELLOW, CLEAR_COLOR);
+            return;
+        }
+
+        println!("{}{:<4} {:<6} {:<4} {:<24} {:<6} {:<6} condition{}",
+          BEGIN_YELLOW, "num", "type", "enb", "address", "hits", "ignore",
+          CLEAR_COLOR);
+        for bp in &self.breakpoints {
+            println!("{:<4} {:<6} {:<4} {:<24} {:<6} {:<6} {}",
+              bp.id,
+              if bp.temporary { "tbreak" } else { "break" },
+              if bp.enabled { "y" } else { "n" },
+              self.map.describe_addr(bp.addr),
+              bp.hits,
+              bp.ignore,
+              bp.condition.as_ref().map_or("", |(source, _)| source));
+        }
+        for wp in &self.watchpoints {
+            println!("{:<4} {:<6} {:<4} {:<24} {:<6} {:<6} value {}",
+              wp.id, "watch", "y", self.map.describe_addr(wp.addr), "", "",
+              wp.value);
+        }
+    }
+
+    fn session_json(&self) -> Value {
+        let frames: Vec<_> = self.vm.frames().iter()
+          .map(|f| json!({
+              "call_site": f.call_site,
+              "target": f.target,
+              "return_addr": f.return_addr,
+              "stack_depth": f.stack_depth,
+          }))
+          .collect();
+
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort();
+        let labels: Vec<_> = labels.into_iter()
+          .map(|(addr, name)| json!({ "addr": addr, "name": name }))
+          .collect();
+
+        let breakpoints: Vec<_> = self.breakpoints.iter()
+          .map(|bp| json!({
+              "id": bp.id,
+              "addr": bp.addr,
+              "condition": bp.condition.as_ref().map(|(source, _)| source),
+              "enabled": bp.enabled,
+              "temporary": bp.temporary,
+              "hits": bp.hits,
+              "ignore": bp.ignore,
+          }))
+          .collect();
+
+        let watchpoints: Vec<_> = self.watchpoints.iter()
+          .map(|wp| json!({ "id": wp.id, "addr": wp.addr, "value": wp.value }))
+          .collect();
+
+        json!({
+            "vm": {
+                "ip": self.vm.ip(),
+                "registers": self.vm.registers(),
+                "stack": self.vm.stack(),
+                "frames": frames,
+                "memory": &self.vm.memory()[..],
+            },
+            "labels": labels,
+            "breakpoints": breakpoints,
+            "watchpoints": watchpoints,
+            "next_breakpoint": self.next_breakpoint,
+            "input": self.in_cursor.get_ref(),
+            "input_position": self.in_cursor.position(),
+            "output": self.transcript,
+            "pending_output": self.out_buf,
+            "aliases": self.aliases,
+            "definitions": self.definitions,
+        })
+    }
+
+    fn save_session(&self, path: &Path) -> Result<(), TracerError> {
+        let mut w = File::create(path)?;
+        serde_json::to_writer(&mut w, &self.session_json())
+          .map_err(io::Error::from)?;
+        writeln!(w)?;
+        println!("{}saved session to {}{}",
+          BEGIN_YELLOW, path.display(), CLEAR_COLOR);
+        Ok(())
+    }
+
+    // everything is checked before any of it replaces the current session
+    fn load_session(&mut self, path: &Path) -> Result<(), TracerError> {
+        let session: Value = serde_json::from_reader(
+          BufReader::new(File::open(path)?))
+          .map_err(|e| TracerError::BadSession(e.to_string()))?;
+
+        let vm_json = json_field(&session, "vm")?;
+        let mut vm = Vm::new();
+        let memory = json_words(vm_json, "memory")?;
+        if memory.len() > vm.memory().len() {
+            return Err(TracerError::BadSession("memory too large".to_string()));
+        }
+        vm.memory_mut()[..memory.len()].copy_from_slice(&memory);
+        let registers = json_words(vm_json, "registers")?;
+        if registers.len() != vm.registers().len() {
+            return Err(TracerError::BadSession(
+              "wrong number of registers".to_string()));
+        }
+        vm.registers_mut().copy_from_slice(&registers);
+        let mem_len = vm.memory().len();
+        vm.jump_to(json_addr(vm_json, "ip", mem_len)?);
+        *vm.stack_mut() = json_words(vm_json, "stack")?;
+        for frame in json_array(vm_json, "frames")? {
+            vm.frames_mut().push(Frame {
+                call_site: json_addr(frame, "call_site", mem_len)?,
+                target: json_addr(frame, "target", mem_len)?,
+                return_addr: json_addr(frame, "return_addr", mem_len)?,
+                stack_depth: json_usize(frame, "stack_depth")?,
+            });
+        }
+
+        let mut labels = Labels::new();
+        for label in json_array(&session, "labels")? {
+            labels.insert(json_usize(label, "addr")?,
+              json_str(label, "name")?.to_string());
+        }
+
+        // conditions see the labels they were written against
+        let map = ImageMap::new(vm.memory(),
+          &Self::map_opts(&self.project, &labels, self.autolabel));
+        let mut all_labels = map.labels.clone();
+        all_labels.extend(labels.clone());
+
+        let mut breakpoints = Vec::new();
+        for bp in json_array(&session, "breakpoints")? {
+            let condition = match json_field(bp, "condition")? {
+                Value::Null => None,
+                source => {
+                    let source = source.as_str().ok_or_else(||
+                      TracerError::BadSession("bad condition".to_string()))?;
+                    Some((source.to_string(),
+                      Expr::parse(source, &all_labels)?))
+                },
+            };
+            breakpoints.push(Breakpoint {
+                id: json_usize(bp, "id")?,
+                addr: json_addr(bp, "addr", mem_len)?,
+                condition,
+                enabled: json_bool(bp, "enabled")?,
+                temporary: json_bool(bp, "temporary")?,
+                hits: json_usize(bp, "hits")?,
+                ignore: json_usize(bp, "ignore")?,
+            });
+        }
+
+        let mut watchpoints = Vec::new();
+        for wp in json_array(&session, "watchpoints")? {
+            let addr = json_addr(wp, "addr", mem_len)?;
+            let value = json_usize(wp, "value")?;
+            if value > u16::MAX as usize {
+                return Err(TracerError::BadSession(
+                  "bad watchpoint".to_string()));
+            }
+            watchpoints.push(Watchpoint {
+                id: json_usize(wp, "id")?,
+                addr,
+                value: value as u16,
+            });
+        }
+
+        let strings = |key| -> Result<HashMap<String, String>, TracerError> {
+            json_field(&session, key)?.as_object()
+              .ok_or_else(|| TracerError::BadSession(
+                format!("\"{}\" isn't an object", key)))?
+              .iter()
+              .map(|(name, text)| text.as_str()
+                .map(|text| (name.clone(), text.to_string()))
+                .ok_or_else(|| TracerError::BadSession(
+                  format!("bad entry in \"{}\"", key))))
+              .collect()
+        };
+        let aliases = strings("aliases")?;
+        let definitions = strings("definitions")?;
+
+        let input = json_bytes(&session, "input")?;
+        let input_position = json_usize(&session, "input_position")?;
+        if input_position > input.len() {
+            return Err(TracerError::BadSession(
+              "\"input_position\" is past the input".to_string()));
+        }
+        let mut in_cursor = Cursor::new(input);
+        in_cursor.set_position(input_position as u64);
+        let transcript = json_bytes(&session, "output")?;
+        let out_buf = json_bytes(&session, "pending_output")?;
+        let next_breakpoint = json_usize(&session, "next_breakpoint")?;
+
+        self.vm = vm;
+        self.labels = labels;
+        self.map = map;
+        self.breakpoints = breakpoints;
+        self.watchpoints = watchpoints;
+        self.next_breakpoint = next_breakpoint;
+        self.aliases = aliases;
+        self.definitions = definitions;
+        self.in_cursor = in_cursor;
+        self.transcript = transcript;
+        self.out_buf = out_buf;
+
+        // where things were left, from the player's side
+        print!("{}output> {}", BEGIN_GREEN, CLEAR_COLOR);
+        io::stdout().write_all(&self.transcript)?;
+        println!("{}loaded session from {}{}",
+          BEGIN_YELLOW, path.display(), CLEAR_COLOR);
+        Ok(())
+    }
+
     fn do_cmd(&mut self, command: TracerCommand
       ) -> Result<TracerState, TracerError> {
         let state = match command {
             TracerCommand::Step => {
                 self.step(true)?;
+                self.check_watchpoints();
                 TracerState::WaitCommand
             },
 
             TracerCommand::Continue(til) => {
                 let til = til.unwrap_or(usize::MAX);
-                while self.vm.ip() < til {
-                    if self.interrupt.swap(false, Ordering::Relaxed) {
-                        return Ok(TracerState::WaitCommand);
-                    }
+                if self.vm.ip() < til {
+                    self.run_until(|t| t.vm.ip() >= til)?;
+                }
+                TracerState::WaitCommand
+            },
 
-                    match self.step(false)? {
-                        VmState::Halted => break,
-                        _ => { },
-                    };
+            // calls are stepped over by waiting for the frame count to
+            //   come back down, so the callee's use of the stack can't
+            //   fool us
+            TracerCommand::Next => {
+                let depth = self.vm.frames().len();
+                self.run_until(|t| t.vm.frames().len() <= depth)?;
+                TracerState::WaitCommand
+            },
 
-                    if self.breakpoints.contains(&self.vm.ip()) {
-                        return Ok(TracerState::WaitCommand);
-                    }
+            TracerCommand::Finish => {
+                let depth = self.vm.frames().len();
+                if depth == 0 {
+                    println!("{}not in a call{}", BEGIN_RED, CLEAR_COLOR);
+                } else {
+                    self.run_until(|t| t.vm.frames().len() < depth)?;
                 }
                 TracerState::WaitCommand
             },
 
+            // like next, but runs through backward jumps, to get out of loops
+            TracerCommand::Until => {
+                let (depth, start) = (self.vm.frames().len(), self.vm.ip());
+                self.run_until(|t| t.vm.frames().len() < depth
+                  || (t.vm.frames().len() == depth && t.vm.ip() > start))?;
+                TracerState::WaitCommand
+            },
+
             TracerCommand::SetLabel(ptr, label) => {
                 self.labels.insert(ptr, label);
                 TracerState::WaitCommand
@@ -296,20 +1147,115 @@ impl Tracer {
                 let ptr = self.labels.iter()
                     .find(|(_, v)| v.as_str() == label)
                     .map(|(ptr, _)| *ptr);
-                match ptr {
-                    Some(ptr) => { self.labels.remove(&ptr); },
-                    None => { },
-                };
+                if let Some(ptr) = ptr {
+                    self.labels.remove(&ptr);
+                }
                 TracerState::WaitCommand
             },
 
-            TracerCommand::SetBreakpoint(ip) => {
-                self.breakpoints.insert(ip);
+            TracerCommand::SetBreakpoint(addr, condition, temporary) => {
+                let id = self.next_breakpoint;
+                self.next_breakpoint += 1;
+                println!("{}breakpoint {} at {}{}", BEGIN_YELLOW, id,
+                  self.map.describe_addr(addr), CLEAR_COLOR);
+                self.breakpoints.push(Breakpoint {
+                    id,
+                    addr,
+                    condition,
+                    enabled: true,
+                    temporary,
+                    hits: 0,
+                    ignore: 0,
+                });
                 TracerState::WaitCommand
             },
 
             TracerCommand::ClearBreakpoint(ip) => {
-                self.breakpoints.remove(&ip);
+                self.breakpoints.retain(|bp| bp.addr != ip);
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::DeleteBreakpoint(id) => {
+                self.breakpoints.retain(|bp| bp.id != id);
+                self.watchpoints.retain(|wp| wp.id != id);
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Watch(addr) => {
+                let id = self.next_breakpoint;
+                self.next_breakpoint += 1;
+                println!("{}watchpoint {} at {}{}", BEGIN_YELLOW, id,
+                  self.map.describe_addr(addr), CLEAR_COLOR);
+                self.watchpoints.push(Watchpoint {
+                    id,
+                    addr,
+                    value: self.vm.memory()[addr],
+                });
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Condition(id, condition) => {
+                self.breakpoint_mut(id)?.condition = condition;
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Ignore(id, count) => {
+                self.breakpoint_mut(id)?.ignore = count;
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Enable(id, enabled) => {
+                self.breakpoint_mut(id)?.enabled = enabled;
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Examine(addr, count, format) => {
+                self.examine(addr, count, format);
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Disassemble(start, count) => {
+                self.disassemble(start, count)?;
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Stack => {
+                self.stack();
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Source(path) => self.source(&path)?,
+
+            TracerCommand::Alias(name, text) => {
+                self.definitions.remove(&name);
+                self.aliases.insert(name, text);
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Define(name, body) => {
+                self.aliases.remove(&name);
+                self.definitions.insert(name, body);
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Unalias(name) => {
+                self.aliases.remove(&name);
+                self.definitions.remove(&name);
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::ListAliases => {
+                self.list_aliases();
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Backtrace => {
+                self.backtrace();
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::InfoBreakpoints => {
+                self.info_breakpoints();
                 TracerState::WaitCommand
             },
 
@@ -350,37 +1296,153 @@ impl Tracer {
                 TracerState::WaitCommand
             },
 
+            TracerCommand::Xrefs(ptr) => {
+                match self.map.xrefs.get(&ptr) {
+                    Some(refs) => for xref in refs {
+                        println!("{} from {}{}{}", xref.kind,
+                          BEGIN_BLUE, self.map.describe_addr(xref.from),
+                          CLEAR_COLOR);
+                    },
+                    None => println!("{}no xrefs to {}{}",
+                      BEGIN_RED, self.map.describe_addr(ptr), CLEAR_COLOR),
+                };
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Save(path) => {
+                if let Err(e) = self.save(path) {
+                    println!("{}{}{}", BEGIN_RED, e, CLEAR_COLOR);
+                }
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::Load(path) => {
+                if let Err(e) = self.load(path) {
+                    println!("{}{}{}", BEGIN_RED, e, CLEAR_COLOR);
+                }
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::SaveSession(path) => {
+                if let Err(e) = self.save_session(&path) {
+                    println!("{}{}{}", BEGIN_RED, e, CLEAR_COLOR);
+                }
+                TracerState::WaitCommand
+            },
+
+            TracerCommand::LoadSession(path) => {
+                if let Err(e) = self.load_session(&path) {
+                    println!("{}{}{}", BEGIN_RED, e, CLEAR_COLOR);
+                }
+                TracerState::WaitCommand
+            },
+
             TracerCommand::Help => {
                 println!("{}syntrace - tracer commands:", BEGIN_YELLOW);
                 println!("  (s)tep");
+                println!("  (n)ext");
+                println!("  (f)inish");
+                println!("  until");
                 println!("  (l)abel <ptr> <lbl>");
                 println!("  (u)nlabel <ptr>");
-                println!("  clea(r) <breakpoint>");
-                println!("  (b)reak <ptr>");
+                println!("  clea(r) <ptr>");
+                println!("  (b)reak <ptr> [if <cond>]");
+                println!("  tbreak <ptr> [if <cond>]");
+                println!("  watch <ptr>");
+                println!("  (d)elete <n>");
+                println!("  condition <n> [<cond>]");
+                println!("  ignore <n> <count>");
+                println!("  enable <n>");
+                println!("  disable <n>");
+                println!("  info breakpoints");
+                println!("  backtrace");
                 println!("  (c)ontinue <ptr>");
                 println!("  push <val>");
                 println!("  pop");
                 println!("  poke <ptr> <val>");
+                println!("  x <ptr> [count] [x|d|c|s]");
+                println!("  stack");
+                println!("  dis [ptr] [count]");
                 println!("  se(t) [r0-r7] <val>");
                 println!("  st(a)tus");
                 println!("  re(m)ap");
+                println!("  xrefs <ptr>");
+                println!("  save [file]");
+                println!("  load [file]");
+                println!("  session save|load <file>");
+                println!("  source <file>");
+                println!("  alias [<name> <command>]");
+                println!("  define <name> <command>[; <command>...]");
+                println!("  unalias <name>");
                 println!("  (h)elp");
                 println!("  (q)uit{}", CLEAR_COLOR);
                 println!();
                 TracerState::WaitCommand
             },
 
+            TracerCommand::Quit if self.autosave && self.is_dirty() => {
+                // don't lose labels on the way out; if they can't be
+                //   saved, a second quit discards them
+                let res = match self.map_path {
+                    Some(_) => self.save(None),
+                    None => Err(TracerError::NoMapFile),
+                };
+                match res {
+                    Ok(_) => TracerState::Quit,
+                    Err(e) => {
+                        println!("{}unsaved labels: {}; quit again to discard{}",
+                          BEGIN_RED, e, CLEAR_COLOR);
+                        self.autosave = false;
+                        TracerState::WaitCommand
+                    },
+                }
+            },
+
             TracerCommand::Quit => TracerState::Quit,
         };
         Ok(state)
     }
 
-    fn get_command(&self) -> Result<TracerCommand, TracerError> {
-        let mut line = String::new();
-        print!("ictrace> ");
-        io::stdout().flush()?;
-        io::stdin().read_line(&mut line)?;
+    fn get_command(&mut self) -> Result<Option<String>, TracerError> {
+        if self.editor.is_none() {
+            let mut line = String::new();
+            print!("ictrace> ");
+            io::stdout().flush()?;
+            if io::stdin().read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            return Ok(Some(line));
+        }
+
+        let helper = TracerHelper {
+            commands: COMMANDS.iter().map(|c| c.to_string())
+              .chain(self.aliases.keys().cloned())
+              .chain(self.definitions.keys().cloned())
+              .collect(),
+            symbols: REGISTERS.iter().map(|r| r.to_string())
+              .chain(self.labels.values().cloned())
+              .collect(),
+        };
+        let editor = self.editor.as_mut().expect("no line editor");
+        editor.set_helper(Some(helper));
+
+        loop {
+            match editor.readline("ictrace> ") {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        editor.add_history_entry(line.as_str())?;
+                    }
+                    return Ok(Some(line));
+                },
+                // ^C abandons the line, like in a shell
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+        }
+    }
 
+    fn parse_command(&self, line: &str) -> Result<TracerCommand, TracerError> {
         let cmd = line.trim();
         if cmd.is_empty() {
             return Ok(TracerCommand::Step);
@@ -394,6 +1456,12 @@ impl Tracer {
                 TracerCommand::Step
             },
 
+            "n" | "next" => TracerCommand::Next,
+
+            "f" | "finish" => TracerCommand::Finish,
+
+            "until" => TracerCommand::Until,
+
             "c" | "continue" => {
                 let ptr = cmd_words.next()
                     .map(|ptr| self.ptr_or_label(ptr))
@@ -416,11 +1484,114 @@ impl Tracer {
                 TracerCommand::ClearLabel(label.to_string())
             },
 
-            "b" | "break" => {
+            "b" | "break" | "tbreak" => {
+                let ptr = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                let ptr = self.ptr_or_label(ptr)?;
+                let condition = match cmd_words.next() {
+                    Some("if") => Some(self.parse_condition(rest_of(cmd, 3))?),
+                    Some(_) => return Err(
+                      TracerError::UnknownCommand(cmd.to_string())),
+                    None => None,
+                };
+                TracerCommand::SetBreakpoint(ptr, condition,
+                  cmd_word == "tbreak")
+            },
+
+            "d" | "delete" => {
+                let id = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                let id = id.parse::<usize>()
+                  .map_err(|_| TracerError::UnknownCommand(cmd.to_string()))?;
+                if !self.breakpoints.iter().any(|bp| bp.id == id)
+                  && !self.watchpoints.iter().any(|wp| wp.id == id) {
+                    return Err(TracerError::UnknownBreakpoint(id));
+                }
+                TracerCommand::DeleteBreakpoint(id)
+            },
+
+            "watch" => {
+                let ptr = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                let ptr = self.ptr_or_label(ptr)?;
+                if ptr >= self.vm.memory().len() {
+                    return Err(TracerError::UnknownCommand(cmd.to_string()));
+                }
+                TracerCommand::Watch(ptr)
+            },
+
+            "condition" => {
+                let id = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                let id = self.breakpoint_id(id)?;
+                let condition = match rest_of(cmd, 2) {
+                    "" => None,
+                    source => Some(self.parse_condition(source)?),
+                };
+                TracerCommand::Condition(id, condition)
+            },
+
+            "ignore" => {
+                let id = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                let id = self.breakpoint_id(id)?;
+                let count = cmd_words.next()
+                  .and_then(|v| v.parse::<usize>().ok())
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                TracerCommand::Ignore(id, count)
+            },
+
+            "enable" | "disable" => {
+                let id = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                let id = self.breakpoint_id(id)?;
+                TracerCommand::Enable(id, cmd_word == "enable")
+            },
+
+            "bt" | "backtrace" => TracerCommand::Backtrace,
+
+            "info" => match cmd_words.next() {
+                Some("b" | "break" | "breakpoints") =>
+                  TracerCommand::InfoBreakpoints,
+                _ => return Err(TracerError::UnknownCommand(cmd.to_string())),
+            },
+
+            "x" | "examine" => {
                 let ptr = cmd_words.next()
                   .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
                 let ptr = self.ptr_or_label(ptr)?;
-                TracerCommand::SetBreakpoint(ptr)
+                let (mut count, mut format) = (None, ExamineFormat::Hex);
+                for word in cmd_words {
+                    format = match word {
+                        "x" | "hex" => ExamineFormat::Hex,
+                        "d" | "dec" => ExamineFormat::Decimal,
+                        "c" | "char" => ExamineFormat::Char,
+                        "s" | "str" => ExamineFormat::String,
+                        _ => {
+                            count = Some(word.parse::<usize>().map_err(|_|
+                              TracerError::UnknownCommand(cmd.to_string()))?);
+                            continue;
+                        },
+                    };
+                }
+                let count = match format {
+                    ExamineFormat::String => count.unwrap_or(1),
+                    _ => count.unwrap_or(8),
+                };
+                TracerCommand::Examine(ptr, count, format)
+            },
+
+            "stack" => TracerCommand::Stack,
+
+            "dis" | "disassemble" => {
+                let start = cmd_words.next()
+                  .map(|ptr| self.ptr_or_label(ptr))
+                  .transpose()?;
+                let count = cmd_words.next()
+                  .map(|v| v.parse::<usize>())
+                  .transpose()
+                  .map_err(|_| TracerError::UnknownCommand(cmd.to_string()))?;
+                TracerCommand::Disassemble(start, count.unwrap_or(10))
             },
 
             "r" | "clear" => {
@@ -474,6 +1645,66 @@ impl Tracer {
 
             "m" | "remap" => TracerCommand::Remap,
 
+            "xrefs" => {
+                let ptr = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                let ptr = self.ptr_or_label(ptr)?;
+                TracerCommand::Xrefs(ptr)
+            },
+
+            "save" => TracerCommand::Save(cmd_words.next().map(PathBuf::from)),
+
+            "load" => TracerCommand::Load(cmd_words.next().map(PathBuf::from)),
+
+            "session" => {
+                let path = match rest_of(cmd, 2) {
+                    "" => return Err(
+                      TracerError::UnknownCommand(cmd.to_string())),
+                    path => PathBuf::from(path),
+                };
+                match cmd_words.next() {
+                    Some("save") => TracerCommand::SaveSession(path),
+                    Some("load") => TracerCommand::LoadSession(path),
+                    _ => return Err(
+                      TracerError::UnknownCommand(cmd.to_string())),
+                }
+            },
+
+            "source" => match rest_of(cmd, 1) {
+                "" => return Err(TracerError::UnknownCommand(cmd.to_string())),
+                path => TracerCommand::Source(PathBuf::from(path)),
+            },
+
+            "alias" | "define" => {
+                let name = match cmd_words.next() {
+                    Some(name) => name.to_string(),
+                    None if cmd_word == "alias" =>
+                      return Ok(TracerCommand::ListAliases),
+                    None => return Err(
+                      TracerError::UnknownCommand(cmd.to_string())),
+                };
+                let text = match rest_of(cmd, 2) {
+                    "" => return Err(
+                      TracerError::UnknownCommand(cmd.to_string())),
+                    text => text.to_string(),
+                };
+                if cmd_word == "alias" {
+                    TracerCommand::Alias(name, text)
+                } else {
+                    TracerCommand::Define(name, text)
+                }
+            },
+
+            "unalias" => {
+                let name = cmd_words.next()
+                  .ok_or_else(|| TracerError::UnknownCommand(cmd.to_string()))?;
+                if !self.aliases.contains_key(name)
+                  && !self.definitions.contains_key(name) {
+                    return Err(TracerError::UnknownAlias(name.to_string()));
+                }
+                TracerCommand::Unalias(name.to_string())
+            },
+
             "h" | "help" => TracerCommand::Help,
 
             "q" | "quit" => TracerCommand::Quit,
@@ -484,6 +1715,23 @@ impl Tracer {
         Ok(res)
     }
 
+    // autolabels can be used in conditions too, though ours win
+    fn parse_condition(&self, source: &str) -> Result<Condition, TracerError> {
+        let mut labels = self.map.labels.clone();
+        labels.extend(self.labels.clone());
+        Ok((source.to_string(), Expr::parse(source, &labels)?))
+    }
+
+    fn breakpoint_id(&self, input: &str) -> Result<usize, TracerError> {
+        let id = input.parse::<usize>()
+          .map_err(|_| TracerError::UnknownCommand(input.to_string()))?;
+        if self.breakpoints.iter().any(|bp| bp.id == id) {
+            Ok(id)
+        } else {
+            Err(TracerError::UnknownBreakpoint(id))
+        }
+    }
+
     #[inline]
     fn ptr_or_label(&self, input: &str) -> Result<usize, TracerError> {
         if let Ok(ptr) = input.parse::<usize>() {
@@ -502,16 +1750,39 @@ impl Tracer {
 pub enum TracerCommand {
     Step,
     Continue(Option<usize>),
+    Next,
+    Finish,
+    Until,
     SetLabel(usize, String),
     ClearLabel(String),
-    SetBreakpoint(usize),
+    SetBreakpoint(usize, Option<Condition>, bool),
     ClearBreakpoint(usize),
+    DeleteBreakpoint(usize),
+    Watch(usize),
+    Condition(usize, Option<Condition>),
+    Ignore(usize, usize),
+    Enable(usize, bool),
+    InfoBreakpoints,
+    Backtrace,
     Push(u16),
     Pop,
     Poke(usize, u16),
+    Examine(usize, usize, ExamineFormat),
+    Stack,
+    Disassemble(Option<usize>, usize),
     SetReg(usize, u16),
     Status,
     Remap,
+    Xrefs(usize),
+    Save(Option<PathBuf>),
+    Load(Option<PathBuf>),
+    SaveSession(PathBuf),
+    LoadSession(PathBuf),
+    Source(PathBuf),
+    Alias(String, String),
+    Define(String, String),
+    Unalias(String),
+    ListAliases,
     Help,
     Quit,
 }
@@ -539,10 +1810,85 @@ fn set_ansi_console() {
     }
 }
 
-fn main() -> Result<(), TracerError> {
-    if cfg!(windows) {
-        set_ansi_console();
+// the text following the first `n` words of a command
+fn rest_of(cmd: &str, n: usize) -> &str {
+    let mut rest = cmd.trim();
+    for _ in 0..n {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
     }
+    rest
+}
+
+fn json_field<'a>(v: &'a Value, key: &str) -> Result<&'a Value, TracerError> {
+    v.get(key)
+      .ok_or_else(|| TracerError::BadSession(format!("missing \"{}\"", key)))
+}
+
+fn json_usize(v: &Value, key: &str) -> Result<usize, TracerError> {
+    json_field(v, key)?.as_u64()
+      .map(|n| n as usize)
+      .ok_or_else(|| TracerError::BadSession(
+        format!("\"{}\" isn't a number", key)))
+}
+
+// an address that has to land in memory of `len` words
+fn json_addr(v: &Value, key: &str, len: usize) -> Result<usize, TracerError> {
+    let addr = json_usize(v, key)?;
+    if addr >= len {
+        return Err(TracerError::BadSession(
+          format!("\"{}\" is outside memory", key)));
+    }
+    Ok(addr)
+}
+
+fn json_bool(v: &Value, key: &str) -> Result<bool, TracerError> {
+    json_field(v, key)?.as_bool()
+      .ok_or_else(|| TracerError::BadSession(
+        format!("\"{}\" isn't a boolean", key)))
+}
+
+fn json_str<'a>(v: &'a Value, key: &str) -> Result<&'a str, TracerError> {
+    json_field(v, key)?.as_str()
+      .ok_or_else(|| TracerError::BadSession(
+        format!("\"{}\" isn't a string", key)))
+}
+
+fn json_array<'a>(v: &'a Value, key: &str
+  ) -> Result<&'a Vec<Value>, TracerError> {
+    json_field(v, key)?.as_array()
+      .ok_or_else(|| TracerError::BadSession(
+        format!("\"{}\" isn't an array", key)))
+}
+
+fn json_words(v: &Value, key: &str) -> Result<Vec<u16>, TracerError> {
+    json_array(v, key)?.iter()
+      .map(|word| word.as_u64()
+        .filter(|&word| word <= u16::MAX as u64)
+        .map(|word| word as u16)
+        .ok_or_else(|| TracerError::BadSession(
+          format!("bad word in \"{}\"", key))))
+      .collect()
+}
+
+fn json_bytes(v: &Value, key: &str) -> Result<Vec<u8>, TracerError> {
+    json_array(v, key)?.iter()
+      .map(|byte| byte.as_u64()
+        .filter(|&byte| byte <= u8::MAX as u64)
+        .map(|byte| byte as u8)
+        .ok_or_else(|| TracerError::BadSession(
+          format!("bad byte in \"{}\"", key))))
+      .collect()
+}
+
+fn read_project(path: &Path) -> Result<ProjectMap, AsmError> {
+    let map_file = File::open(path)?;
+    ProjectMap::read(&mut BufReader::new(map_file))
+}
+
+fn main() -> Result<(), TracerError> {
+    #[cfg(windows)]
+    set_ansi_console();
 
     let options = Options::from_args();
 
@@ -560,14 +1906,9 @@ fn main() -> Result<(), TracerError> {
       BEGIN_RED, CLEAR_COLOR, BEGIN_YELLOW, CLEAR_COLOR,
       BEGIN_BLUE, CLEAR_COLOR);
 
-    let initial_labels = {
-        if let Some(path) = options.map_file {
-            let map_file = File::open(path)?;
-            let mut map_file = BufReader::new(map_file);
-            Some(read_labels(&mut map_file)?)
-        } else {
-            None
-        }
+    let project = match &options.map_file {
+        Some(path) => read_project(path)?,
+        None => ProjectMap::new(),
     };
 
     let initial_input = if let Some(path) = options.initial_input {
@@ -578,10 +1919,14 @@ fn main() -> Result<(), TracerError> {
         None
     };
 
-    let mut tracer = Tracer::new(vm, initial_labels, initial_input,
-      options.autolabel);
+    let mut tracer = Tracer::new(vm, options.map_file, project, initial_input,
+      options.autolabel, !options.no_autosave,
+      options.style.unwrap_or_default());
+    let history = options.history.or_else(|| env::var_os("HOME")
+      .map(|home| Path::new(&home).join(".syntrace_history")));
+    tracer.init_editor(history)?;
     tracer.register_sigint()?;
-    tracer.run()?;
+    tracer.run(options.script.as_deref(), options.batch)?;
 
     Ok(())
 }
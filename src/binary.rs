@@ -3,7 +3,7 @@ use std::convert::TryInto;
 use super::vm::{Error, Result};
 
 pub fn read_binary(binary: &[u8]) -> Result<Vec<u16>> {
-    if binary.len() % 2 != 0 {
+    if !binary.len().is_multiple_of(2) {
         Err(Error::BadBinary)
     } else {
         Ok(binary.chunks(2)
@@ -11,3 +11,9 @@ pub fn read_binary(binary: &[u8]) -> Result<Vec<u16>> {
           .collect())
     }
 }
+
+pub fn write_binary(image: &[u16]) -> Vec<u8> {
+    image.iter()
+      .flat_map(|w| w.to_le_bytes().to_vec())
+      .collect()
+}
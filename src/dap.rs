@@ -0,0 +1,858 @@
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use super::{
+    asm::{parse_register, AsmItem, DisAsm, DisAsmOpts, ImageMap},
+    binary,
+    expr::Expr,
+    map::ProjectMap,
+    vm::{self, Instruction, Vm, VmState},
+};
+
+// the debug adapter protocol: JSON messages, each after a
+//   `Content-Length` header. memory references are word addresses, and
+//   there's one thread. the game's output goes to the debug console, and
+//   whatever's typed there is the game's input; expressions typed there
+//   start with `=`
+
+const THREAD_ID: u64 = 1;
+
+const REGISTERS_REF: u64 = 1;
+const STACK_REF: u64 = 2;
+const MEMORY_REF: u64 = 3;
+// one reference per page of memory, from here up
+const PAGE_REF_BASE: u64 = 1000;
+const PAGE_SIZE: usize = 256;
+
+// how often a running target checks for a pause request
+const POLL_INTERVAL: usize = 1024;
+
+#[derive(Debug)]
+pub enum DapError {
+    IOError(io::Error),
+    BadMessage(String),
+    // the client hung up without disconnecting
+    Disconnected,
+}
+
+impl From<io::Error> for DapError {
+    fn from(other: io::Error) -> Self {
+        DapError::IOError(other)
+    }
+}
+
+impl fmt::Display for DapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DapError::IOError(e) => write!(f, "I/O error: {}", e),
+            DapError::BadMessage(e) => write!(f, "bad message: {}", e),
+            DapError::Disconnected => write!(f, "client disconnected"),
+        }
+    }
+}
+
+impl error::Error for DapError { }
+
+pub type Result<T> = std::result::Result<T, DapError>;
+
+// a response body, or the message for a failed request
+type Reply = std::result::Result<Value, String>;
+
+type Requests = Receiver<Result<Value>>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RunMode {
+    StepIn,
+    // until the call stack is no deeper than this
+    StepOver(usize),
+    // until it's shallower
+    StepOut(usize),
+    Continue,
+}
+
+// why a running target stopped to look at its requests
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Interrupt {
+    Pause,
+    // the client's leaving; its request is next in `pending`
+    Leave,
+}
+
+struct Breakpoint {
+    id: u64,
+    addr: usize,
+    condition: Option<Expr>,
+}
+
+pub struct DapServer {
+    // an image for `attach`, from whoever started us
+    image: Option<Vm>,
+    vm: Option<Vm>,
+    project: ProjectMap,
+    // names for addresses, and labels for expressions
+    map: ImageMap,
+    // where instructions start in live memory, for `disassemble`; dropped
+    //   whenever memory is written
+    code_map: Option<ImageMap>,
+    function_breakpoints: Vec<Breakpoint>,
+    instruction_breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: u64,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    stop_on_entry: bool,
+    // how we were running when the game ran out of input
+    waiting: Option<RunMode>,
+    halted: bool,
+    seq: u64,
+    // requests which arrived while the target was running
+    pending: VecDeque<Value>,
+}
+
+impl DapServer {
+    pub fn new(image: Option<Vm>) -> Self {
+        Self {
+            image,
+            vm: None,
+            project: ProjectMap::new(),
+            map: ImageMap::new(&[], &DisAsmOpts::default()),
+            code_map: None,
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            input: VecDeque::new(),
+            output: Vec::new(),
+            stop_on_entry: false,
+            waiting: None,
+            halted: false,
+            seq: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub fn vm(&self) -> Option<&Vm> {
+        self.vm.as_ref()
+    }
+
+    // answers requests until the client disconnects; requests are read
+    //   on their own thread, so a running target can be paused
+    pub fn serve<R, W>(&mut self, input: R, out: &mut W) -> Result<()>
+      where R: BufRead + Send + 'static, W: Write {
+        let requests = spawn_reader(input);
+        loop {
+            let msg = match self.pending.pop_front() {
+                Some(msg) => msg,
+                None => match requests.recv() {
+                    Ok(msg) => msg?,
+                    Err(_) => return Err(DapError::Disconnected),
+                },
+            };
+            if msg["type"] != "request" {
+                continue;
+            }
+            if !self.handle(&msg, &requests, out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // false once we've been told to go away
+    fn handle<W: Write>(&mut self, req: &Value, requests: &Requests,
+      out: &mut W) -> Result<bool> {
+        let command = req["command"].as_str().unwrap_or("");
+        let args = &req["arguments"];
+        let mut resume = None;
+        let reply = match command {
+            "initialize" => Ok(capabilities()),
+            "launch" => self.launch(args),
+            "attach" => self.attach(args),
+            "configurationDone" => {
+                if !self.stop_on_entry {
+                    resume = Some(RunMode::Continue);
+                }
+                Ok(Value::Null)
+            },
+            "disconnect" | "terminate" => {
+                self.respond(out, req, Ok(Value::Null))?;
+                return Ok(false);
+            },
+
+            "setBreakpoints" => Ok(source_breakpoints(args)),
+            "setFunctionBreakpoints" => {
+                let (bps, body) = self.breakpoints(args, |server, spec| {
+                    let name = spec["name"].as_str().unwrap_or("");
+                    server.lookup(name)
+                      .ok_or_else(|| format!("unknown label {}", name))
+                });
+                self.function_breakpoints = bps;
+                Ok(body)
+            },
+            "setInstructionBreakpoints" => {
+                let (bps, body) = self.breakpoints(args, |_, spec| {
+                    let addr = spec["instructionReference"].as_str()
+                      .and_then(parse_number)
+                      .ok_or("bad instruction reference")?;
+                    let offset = spec["offset"].as_i64().unwrap_or(0);
+                    usize::try_from(addr as i64 + offset)
+                      .map_err(|_| "bad instruction reference".to_string())
+                });
+                self.instruction_breakpoints = bps;
+                Ok(body)
+            },
+
+            "threads" =>
+              Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(scopes()),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "disassemble" => self.disassemble(args),
+
+            "continue" | "next" | "stepIn" | "stepOut" =>
+              self.run_mode(command).map(|mode| {
+                  resume = Some(mode);
+                  match mode {
+                      RunMode::Continue =>
+                        json!({ "allThreadsContinued": true }),
+                      _ => Value::Null,
+                  }
+              }),
+            // we only see this while stopped
+            "pause" => Ok(Value::Null),
+
+            _ => Err(format!("unsupported request '{}'", command)),
+        };
+
+        let ok = reply.is_ok();
+        self.respond(out, req, reply)?;
+        if !ok {
+            return Ok(true);
+        }
+
+        match command {
+            "launch" | "attach" => self.event(out, "initialized", Value::Null)?,
+            "configurationDone" if self.stop_on_entry =>
+              self.stopped(out, "entry", None, &[])?,
+            // the game had been waiting for what was just typed
+            "evaluate" if !self.input.is_empty() =>
+              resume = resume.or_else(|| self.waiting.take()),
+            _ => { },
+        };
+
+        if let Some(mode) = resume {
+            self.resume(mode, requests, out)?;
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Reply {
+        let program = args["program"].as_str().ok_or("launch needs a program")?;
+        let prog = fs::read(program)
+          .map_err(|e| e.to_string())
+          .and_then(|image| binary::read_binary(&image)
+            .map_err(|e| e.to_string()))
+          .map_err(|e| format!("{}: {}", program, e))?;
+        let mut vm = Vm::new();
+        vm.load(&prog).map_err(|e| format!("{}: {}", program, e))?;
+        self.start(vm, args, false)
+    }
+
+    fn attach(&mut self, args: &Value) -> Reply {
+        let vm = self.image.take()
+          .ok_or("nothing to attach to; start the adapter with an image")?;
+        self.start(vm, args, true)
+    }
+
+    fn start(&mut self, vm: Vm, args: &Value, stop_on_entry: bool) -> Reply {
+        if let Some(path) = args["map"].as_str() {
+            let project = File::open(path)
+              .map_err(|e| e.to_string())
+              .and_then(|f| ProjectMap::read(&mut BufReader::new(f))
+                .map_err(|e| e.to_string()))
+              .map_err(|e| format!("{}: {}", path, e))?;
+            self.project = project;
+        }
+        if let Some(path) = args["input"].as_str() {
+            let input = fs::read(path)
+              .map_err(|e| format!("{}: {}", path, e))?;
+            self.input.extend(input);
+        }
+
+        self.stop_on_entry = args["stopOnEntry"].as_bool()
+          .unwrap_or(stop_on_entry);
+        self.map = ImageMap::new(vm.memory(), &map_opts(&self.project));
+        self.code_map = None;
+        self.vm = Some(vm);
+        Ok(Value::Null)
+    }
+
+    fn vm_ref(&self) -> std::result::Result<&Vm, String> {
+        self.vm.as_ref().ok_or_else(|| "no program is running".to_string())
+    }
+
+    fn vm_mut(&mut self) -> std::result::Result<&mut Vm, String> {
+        self.vm.as_mut().ok_or_else(|| "no program is running".to_string())
+    }
+
+    // a label, or a number
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.map.labels.iter()
+          .find(|(_, label)| *label == name)
+          .map(|(&addr, _)| addr)
+          .or_else(|| parse_number(name).map(usize::from))
+    }
+
+    // a new set of breakpoints, and the response describing them
+    fn breakpoints<F>(&mut self, args: &Value, resolve: F
+      ) -> (Vec<Breakpoint>, Value)
+      where F: Fn(&Self, &Value) -> std::result::Result<usize, String> {
+        let mut bps = Vec::new();
+        let mut descs = Vec::new();
+        for spec in args["breakpoints"].as_array().into_iter().flatten() {
+            let bp = resolve(self, spec)
+              .and_then(|addr| if addr < self.map_len() {
+                  Ok(addr)
+              } else {
+                  Err(format!("no address {}", addr))
+              })
+              .and_then(|addr| {
+                  let condition = match spec["condition"].as_str() {
+                      Some(cond) => Some(Expr::parse(cond, &self.map.labels)
+                        .map_err(|e| e.to_string())?),
+                      None => None,
+                  };
+                  let id = self.next_breakpoint_id;
+                  Ok(Breakpoint { id, addr, condition })
+              });
+
+            match bp {
+                Ok(bp) => {
+                    self.next_breakpoint_id += 1;
+                    descs.push(json!({
+                        "id": bp.id,
+                        "verified": true,
+                        "instructionReference": addr_ref(bp.addr),
+                    }));
+                    bps.push(bp);
+                },
+                Err(e) =>
+                  descs.push(json!({ "verified": false, "message": e })),
+            };
+        }
+        (bps, json!({ "breakpoints": descs }))
+    }
+
+    fn map_len(&self) -> usize {
+        self.vm.as_ref().map_or(0, |vm| vm.memory().len())
+    }
+
+    // the ids of the breakpoints at `ip` whose conditions hold; one we
+    //   can't evaluate stops us, so it can be fixed
+    fn hits(&self, vm: &Vm) -> Vec<u64> {
+        self.function_breakpoints.iter()
+          .chain(&self.instruction_breakpoints)
+          .filter(|bp| bp.addr == vm.ip())
+          .filter(|bp| bp.condition.as_ref()
+            .is_none_or(|cond| cond.is_true(vm).unwrap_or(true)))
+          .map(|bp| bp.id)
+          .collect()
+    }
+
+    // the current instruction, then each call site on the shadow stack
+    fn stack_trace(&self) -> Reply {
+        let vm = self.vm_ref()?;
+        let addrs: Vec<_> = Some(vm.ip()).into_iter()
+          .chain(vm.frames().iter().rev().map(|frame| frame.call_site))
+          .collect();
+        let frames: Vec<_> = addrs.iter().enumerate()
+          .map(|(id, &addr)| json!({
+              "id": id,
+              "name": self.map.describe_addr(addr),
+              "line": 0,
+              "column": 0,
+              "instructionPointerReference": addr_ref(addr),
+          }))
+          .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": addrs.len() }))
+    }
+
+    fn variables(&self, args: &Value) -> Reply {
+        let vm = self.vm_ref()?;
+        let reference = args["variablesReference"].as_u64().unwrap_or(0);
+        let pages = vm.memory().len() / PAGE_SIZE;
+        let vars: Vec<_> = match reference {
+            REGISTERS_REF => vm.registers().iter().enumerate()
+              .map(|(r, &val)| variable(&format!("r{}", r), val))
+              .chain(Some(variable("ip", vm.ip() as u16)))
+              .collect(),
+
+            // top first
+            STACK_REF => vm.stack().iter().enumerate().rev()
+              .map(|(depth, &val)| variable(&format!("[{}]", depth), val))
+              .collect(),
+
+            MEMORY_REF => (0..pages)
+              .map(|page| json!({
+                  "name": format!("{}..{}", addr_ref(page * PAGE_SIZE),
+                    addr_ref((page + 1) * PAGE_SIZE - 1)),
+                  "value": "",
+                  "variablesReference": PAGE_REF_BASE + page as u64,
+              }))
+              .collect(),
+
+            r if r >= PAGE_REF_BASE && r < PAGE_REF_BASE + pages as u64 => {
+                let start = (r - PAGE_REF_BASE) as usize * PAGE_SIZE;
+                (start..start + PAGE_SIZE)
+                  .map(|addr| {
+                      let name = match self.map.labels.get(&addr) {
+                          Some(label) =>
+                            format!("{} {}", addr_ref(addr), label),
+                          None => addr_ref(addr),
+                      };
+                      variable(&name, vm.memory()[addr])
+                  })
+                  .collect()
+            },
+
+            _ => return Err(format!("no variables for reference {}",
+              reference)),
+        };
+        Ok(json!({ "variables": vars }))
+    }
+
+    fn set_variable(&mut self, args: &Value) -> Reply {
+        let reference = args["variablesReference"].as_u64().unwrap_or(0);
+        let name = args["name"].as_str().unwrap_or("");
+        let text = args["value"].as_str().unwrap_or("");
+        let val = parse_number(text)
+          .ok_or_else(|| format!("bad value {}", text))?;
+
+        if reference >= PAGE_REF_BASE {
+            self.code_map = None;
+        }
+        let map_len = self.map_len();
+        let vm = self.vm_mut()?;
+        let slot = match reference {
+            REGISTERS_REF if name == "ip" => {
+                if val as usize >= map_len {
+                    return Err(format!("no address {}", val));
+                }
+                vm.jump_to(val as usize);
+                return Ok(json!({ "value": word_value(val) }));
+            },
+            REGISTERS_REF => parse_register(name)
+              .map(move |r| &mut vm.registers_mut()[r]),
+            STACK_REF => name.trim_matches(|c| c == '[' || c == ']')
+              .parse::<usize>().ok()
+              .and_then(move |depth| vm.stack_mut().get_mut(depth)),
+            r if r >= PAGE_REF_BASE => name.split_whitespace().next()
+              .and_then(parse_number)
+              .and_then(move |addr| vm.memory_mut().get_mut(addr as usize)),
+            _ => None,
+        };
+        *slot.ok_or_else(|| format!("no variable {}", name))? = val;
+        Ok(json!({ "value": word_value(val) }))
+    }
+
+    // game input from the console, or an expression
+    fn evaluate(&mut self, args: &Value) -> Reply {
+        let text = args["expression"].as_str().unwrap_or("");
+        let source = match (args["context"].as_str(), text.strip_prefix('=')) {
+            (_, Some(source)) => source,
+            (Some("repl"), None) => {
+                self.input.extend(text.bytes());
+                self.input.push_back(b'\n');
+                return Ok(json!({ "result": "", "variablesReference": 0 }));
+            },
+            (_, None) => text,
+        };
+
+        let vm = self.vm_ref()?;
+        let val = Expr::parse(source, &self.map.labels)
+          .and_then(|expr| expr.eval(vm))
+          .map_err(|e| e.to_string())?;
+        Ok(json!({ "result": val.to_string(), "variablesReference": 0 }))
+    }
+
+    // from live memory, with a map of it to find where instructions start
+    fn disassemble(&mut self, args: &Value) -> Reply {
+        let opts = map_opts(&self.project);
+        let vm = self.vm.as_ref().ok_or("no program is running")?;
+        let map = self.code_map
+          .get_or_insert_with(|| ImageMap::new(vm.memory(), &opts));
+        let origin = args["memoryReference"].as_str()
+          .and_then(parse_number)
+          .ok_or("bad memory reference")? as i64
+          + args["offset"].as_i64().unwrap_or(0);
+        let first = args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_u64().unwrap_or(0) as usize;
+
+        let at = map.stmts.iter()
+          .rposition(|(addr, _)| *addr as i64 <= origin)
+          .unwrap_or(0) as i64;
+
+        let mut instrs = Vec::new();
+        for i in (at + first..).take(count) {
+            let (addr, stmt) = match usize::try_from(i).ok()
+              .and_then(|i| map.stmts.get(i)) {
+                Some((addr, stmt)) => (*addr, stmt),
+                None => {
+                    let addr = if i < 0 { 0 } else { vm.memory().len() };
+                    instrs.push(json!({
+                        "address": addr_ref(addr),
+                        "instruction": "",
+                        "presentationHint": "invalid",
+                    }));
+                    continue;
+                },
+            };
+
+            let (size, text) = match stmt {
+                AsmItem::Instruction(instr) => {
+                    let mut text = Vec::new();
+                    instr.disasm(addr, map, &opts, &mut text)
+                      .map_err(|e| e.to_string())?;
+                    (instr.size(),
+                      String::from_utf8_lossy(&text).trim_end().to_string())
+                },
+                stmt => (stmt.size(), format!(".word {}",
+                  opts.style.number(vm.memory()[addr]))),
+            };
+            let bytes: Vec<_> = vm.memory()[addr..addr + size].iter()
+              .map(|word| format!("{:04x}", word))
+              .collect();
+
+            let mut instr = json!({
+                "address": addr_ref(addr),
+                "instructionBytes": bytes.join(" "),
+                "instruction": text,
+            });
+            if let Some(label) = map.labels.get(&addr) {
+                instr["symbol"] = json!(label);
+            }
+            instrs.push(instr);
+        }
+        Ok(json!({ "instructions": instrs }))
+    }
+
+    fn run_mode(&self, command: &str) -> std::result::Result<RunMode, String> {
+        let depth = self.vm_ref()?.frames().len();
+        match command {
+            "stepIn" => Ok(RunMode::StepIn),
+            "next" => Ok(RunMode::StepOver(depth)),
+            "stepOut" if depth == 0 => Err("not in a call".to_string()),
+            "stepOut" => Ok(RunMode::StepOut(depth)),
+            _ => Ok(RunMode::Continue),
+        }
+    }
+
+    fn resume<W: Write>(&mut self, mode: RunMode, requests: &Requests,
+      out: &mut W) -> Result<()> {
+        if self.halted {
+            return self.event(out, "terminated", Value::Null);
+        }
+        self.waiting = None;
+
+        // the first instruction runs even from a breakpoint, or we'd
+        //   never get past one
+        let mut steps = 0;
+        loop {
+            let vm = match &mut self.vm {
+                Some(vm) => vm,
+                None => return Ok(()),
+            };
+            let writes = vm.decode_next()
+              .is_ok_and(|(_, instr)| matches!(instr, Instruction::Wmem(..)));
+            let state = vm.step(&mut self.input, &mut self.output);
+            let mismatch = vm.call_mismatch();
+            let depth = vm.frames().len();
+            if writes {
+                self.code_map = None;
+            }
+
+            match state {
+                Ok(VmState::Running) => { },
+                Ok(VmState::Halted) => {
+                    self.halted = true;
+                    self.flush_output(out)?;
+                    self.event(out, "exited", json!({ "exitCode": 0 }))?;
+                    return self.event(out, "terminated", Value::Null);
+                },
+                // out of input; the `in` can be retried once there's more
+                Err(vm::Error::IOError) => {
+                    self.waiting = Some(mode);
+                    self.flush_output(out)?;
+                    return self.stopped(out, "pause",
+                      Some("waiting for input"), &[]);
+                },
+                Err(e) => {
+                    self.flush_output(out)?;
+                    return self.stopped(out, "exception",
+                      Some(&e.to_string()), &[]);
+                },
+            };
+
+            if let Some(mismatch) = mismatch {
+                self.flush_output(out)?;
+                self.console(out, &format!("{}\n", mismatch))?;
+            }
+            if self.output.ends_with(b"\n") {
+                self.flush_output(out)?;
+            }
+
+            let hits = self.vm.as_ref().map(|vm| self.hits(vm))
+              .unwrap_or_default();
+            if !hits.is_empty() {
+                self.flush_output(out)?;
+                return self.stopped(out, "breakpoint", None, &hits);
+            }
+
+            let done = match mode {
+                RunMode::StepIn => true,
+                RunMode::StepOver(start) => depth <= start,
+                RunMode::StepOut(start) => depth < start,
+                RunMode::Continue => false,
+            };
+            if done {
+                self.flush_output(out)?;
+                return self.stopped(out, "step", None, &[]);
+            }
+
+            steps += 1;
+            if steps % POLL_INTERVAL != 0 {
+                continue;
+            }
+            match self.poll_pause(requests, out)? {
+                Some(Interrupt::Pause) => {
+                    self.flush_output(out)?;
+                    return self.stopped(out, "pause", None, &[]);
+                },
+                Some(Interrupt::Leave) => return self.flush_output(out),
+                None => { },
+            };
+        }
+    }
+
+    // whether we've been asked to pause or to go away; anything else
+    //   waits its turn
+    fn poll_pause<W: Write>(&mut self, requests: &Requests, out: &mut W
+      ) -> Result<Option<Interrupt>> {
+        loop {
+            match requests.try_recv() {
+                Ok(msg) => {
+                    let msg = msg?;
+                    match msg["command"].as_str() {
+                        Some("pause") => {
+                            self.respond(out, &msg, Ok(Value::Null))?;
+                            return Ok(Some(Interrupt::Pause));
+                        },
+                        // ahead of anything waiting, which could only
+                        //   set us running again
+                        Some("disconnect" | "terminate") => {
+                            self.pending.push_front(msg);
+                            return Ok(Some(Interrupt::Leave));
+                        },
+                        _ => self.pending.push_back(msg),
+                    };
+                },
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) =>
+                  return Err(DapError::Disconnected),
+            };
+        }
+    }
+
+    fn flush_output<W: Write>(&mut self, out: &mut W) -> Result<()> {
+        if self.output.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&self.output).into_owned();
+        self.output.clear();
+        self.event(out, "output", json!({
+            "category": "stdout",
+            "output": text,
+        }))
+    }
+
+    fn console<W: Write>(&mut self, out: &mut W, text: &str) -> Result<()> {
+        self.event(out, "output", json!({
+            "category": "console",
+            "output": text,
+        }))
+    }
+
+    fn stopped<W: Write>(&mut self, out: &mut W, reason: &str,
+      text: Option<&str>, hits: &[u64]) -> Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["description"] = json!(text);
+            body["text"] = json!(text);
+        }
+        if !hits.is_empty() {
+            body["hitBreakpointIds"] = json!(hits);
+        }
+        self.event(out, "stopped", body)
+    }
+
+    fn respond<W: Write>(&mut self, out: &mut W, req: &Value, reply: Reply
+      ) -> Result<()> {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": reply.is_ok(),
+        });
+        match reply {
+            Ok(Value::Null) => { },
+            Ok(body) => msg["body"] = body,
+            Err(e) => msg["message"] = json!(e),
+        };
+        self.send(out, msg)
+    }
+
+    fn event<W: Write>(&mut self, out: &mut W, event: &str, body: Value
+      ) -> Result<()> {
+        let mut msg = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            msg["body"] = body;
+        }
+        self.send(out, msg)
+    }
+
+    fn send<W: Write>(&mut self, out: &mut W, mut msg: Value) -> Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        let body = msg.to_string();
+        // in one write, header and all
+        let frame = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        out.write_all(frame.as_bytes())?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsFunctionBreakpoints": true,
+        "supportsConditionalBreakpoints": true,
+        "supportsInstructionBreakpoints": true,
+        "supportsDisassembleRequest": true,
+        "supportsSetVariable": true,
+        "supportsEvaluateForHovers": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+fn scopes() -> Value {
+    json!({ "scopes": [
+        { "name": "Registers", "variablesReference": REGISTERS_REF,
+          "expensive": false },
+        { "name": "Stack", "variablesReference": STACK_REF,
+          "expensive": false },
+        { "name": "Memory", "variablesReference": MEMORY_REF,
+          "expensive": true },
+    ] })
+}
+
+// we've no source to put lines in
+fn source_breakpoints(args: &Value) -> Value {
+    let descs: Vec<_> = args["breakpoints"].as_array().into_iter().flatten()
+      .map(|_| json!({
+          "verified": false,
+          "message": "no source lines; use a function or instruction \
+            breakpoint",
+      }))
+      .collect();
+    json!({ "breakpoints": descs })
+}
+
+fn map_opts(project: &ProjectMap) -> DisAsmOpts {
+    DisAsmOpts {
+        initial_labels: Some(project.labels.clone()),
+        string_hints: project.string_hints.clone(),
+        regions: project.regions.clone(),
+        entries: project.entries.clone(),
+        ..DisAsmOpts::default()
+    }
+}
+
+fn variable(name: &str, val: u16) -> Value {
+    json!({ "name": name, "value": word_value(val), "variablesReference": 0 })
+}
+
+fn word_value(val: u16) -> String {
+    format!("{} (0x{:04x})", val, val)
+}
+
+fn addr_ref(addr: usize) -> String {
+    format!("0x{:04x}", addr)
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    let text = text.trim();
+    match text.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn spawn_reader<R: BufRead + Send + 'static>(mut input: R) -> Requests {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        let msg = match read_message(&mut input) {
+            Ok(Some(msg)) => Ok(msg),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        };
+        let failed = msg.is_err();
+        if tx.send(msg).is_err() || failed {
+            return;
+        }
+    });
+    rx
+}
+
+// `None` at the end of the input
+fn read_message<R: BufRead>(input: &mut R) -> Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, val)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = Some(val.trim().parse::<usize>().map_err(|_|
+                  DapError::BadMessage(format!("bad length {}", val)))?);
+            }
+        }
+    }
+
+    let len = len.ok_or_else(||
+      DapError::BadMessage("no Content-Length header".to_string()))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+      .map(Some)
+      .map_err(|e| DapError::BadMessage(e.to_string()))
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+
+use super::{
+    isa::{FlowClass, Operand},
+    vm::{Instruction, SrcOperand, Vm},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct RegSet(u8);
+
+impl RegSet {
+    #[inline]
+    pub fn new() -> Self {
+        RegSet(0)
+    }
+
+    #[inline]
+    pub fn insert(&mut self, reg: usize) {
+        self.0 |= 1 << reg;
+    }
+
+    #[inline]
+    pub fn contains(&self, reg: usize) -> bool {
+        reg < 8 && self.0 & (1 << reg) != 0
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..8).filter(move |&r| self.contains(r))
+    }
+}
+
+impl fmt::Display for RegSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regs: Vec<_> = self.iter().map(|r| format!("r{}", r)).collect();
+        write!(f, "{{{}}}", regs.join(", "))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct StackEffect {
+    pub pops: usize,
+    pub pushes: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IoEffect {
+    Input,
+    Output,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Successor {
+    Next(usize),
+    Target(SrcOperand),
+    // wherever the popped return address says
+    Return,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Effects {
+    pub reads: RegSet,
+    pub writes: RegSet,
+    // address operands of memory accesses
+    pub mem_read: Option<SrcOperand>,
+    pub mem_write: Option<SrcOperand>,
+    pub stack: StackEffect,
+    pub io: Option<IoEffect>,
+    // empty for halt
+    pub successors: Vec<Successor>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedEffects {
+    pub mem_read: Option<u16>,
+    pub mem_write: Option<u16>,
+    // None where a return would pop an empty stack (and so halt)
+    pub successors: Vec<Option<usize>>,
+}
+
+impl Instruction {
+    pub fn effects(&self, ip: usize) -> Effects {
+        let info = self.info();
+
+        let mut reads = RegSet::new();
+        let mut writes = RegSet::new();
+        for operand in self.operands() {
+            match operand {
+                Operand::Src(SrcOperand::Register(r)) => reads.insert(r),
+                Operand::Src(SrcOperand::Immediate(_)) => { },
+                Operand::Dst(dst) => writes.insert(dst.register()),
+            };
+        }
+
+        let (mem_read, mem_write) = match *self {
+            Instruction::Rmem(_, addr) => (Some(addr), None),
+            Instruction::Wmem(addr, _) => (None, Some(addr)),
+            _ => (None, None),
+        };
+
+        let stack = StackEffect {
+            pops: info.effects.pops as usize,
+            pushes: info.effects.pushes as usize,
+        };
+
+        let io = if info.effects.input {
+            Some(IoEffect::Input)
+        } else if info.effects.output {
+            Some(IoEffect::Output)
+        } else {
+            None
+        };
+
+        let next = Successor::Next(ip + info.size());
+        let successors = match (info.flow, self.target()) {
+            (FlowClass::Sequential, _) => vec![next],
+            (FlowClass::Jump, Some(t)) => vec![Successor::Target(t)],
+            (FlowClass::Branch, Some(t)) => vec![next, Successor::Target(t)],
+            // execution resumes after the call once the callee returns
+            (FlowClass::Call, Some(t)) => vec![Successor::Target(t), next],
+            (FlowClass::Return, _) => vec![Successor::Return],
+            _ => Vec::new(),
+        };
+
+        Effects { reads, writes, mem_read, mem_write, stack, io, successors }
+    }
+}
+
+impl Effects {
+    pub fn resolve(&self, vm: &Vm) -> ResolvedEffects {
+        let value = |src: &SrcOperand| match *src {
+            SrcOperand::Immediate(val) => val,
+            SrcOperand::Register(r) => vm.registers()[r],
+        };
+
+        let successors = self.successors.iter()
+          .map(|s| match s {
+              Successor::Next(ip) => Some(*ip),
+              Successor::Target(t) => Some(value(t) as usize),
+              Successor::Return => vm.stack().last().map(|&ip| ip as usize),
+          })
+          .collect();
+
+        ResolvedEffects {
+            mem_read: self.mem_read.as_ref().map(value),
+            mem_write: self.mem_write.as_ref().map(value),
+            successors,
+        }
+    }
+}
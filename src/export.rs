@@ -0,0 +1,283 @@
+use std::io::{self, Write};
+
+use serde_json::{json, Value};
+
+use super::{
+    asm::{
+        AsmItem, DisAsm, DisAsmError, DisAsmOpts, ImageMap, Word, escape_string,
+    },
+    isa::Operand,
+    map::RegionKind,
+    vm::{Instruction, SrcOperand},
+};
+
+impl ImageMap {
+    fn is_modified(&self, ip: usize, stmt: &AsmItem, opts: &DisAsmOpts
+      ) -> bool {
+        opts.highlights.iter()
+          .any(|r| r.start < ip + stmt.size() && ip < r.end)
+    }
+
+    pub fn to_json(&self, opts: &DisAsmOpts) -> Result<Value, DisAsmError> {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort();
+        let labels: Vec<_> = labels.into_iter()
+          .map(|(addr, name)| json!({ "addr": addr, "name": name }))
+          .collect();
+
+        let regions: Vec<_> = opts.regions.iter()
+          .map(|r| {
+              let (kind, name) = match &r.kind {
+                  RegionKind::Code => ("code", None),
+                  RegionKind::Data => ("data", None),
+                  RegionKind::String => ("string", None),
+                  RegionKind::Struct(name) => ("struct", Some(name)),
+              };
+              json!({ "start": r.start, "len": r.len, "kind": kind,
+                "struct": name })
+          })
+          .collect();
+
+        let mut stmts = Vec::new();
+        for (ip, stmt) in &self.stmts {
+            stmts.push(self.stmt_json(*ip, stmt, opts)?);
+        }
+
+        Ok(json!({
+            "labels": labels,
+            "regions": regions,
+            "statements": stmts,
+        }))
+    }
+
+    fn stmt_json(&self, ip: usize, stmt: &AsmItem, opts: &DisAsmOpts
+      ) -> Result<Value, DisAsmError> {
+        let mut text = Vec::new();
+        match stmt {
+            AsmItem::Instruction(instr) =>
+              instr.disasm(ip, self, opts, &mut text)?,
+            AsmItem::Value(word) => word.disasm(ip, self, opts, &mut text)?,
+            AsmItem::String(kind, chars) => write!(text, "{} \"{}\"",
+              kind.directive(), escape_string(chars))?,
+        };
+        let text = String::from_utf8_lossy(&text).trim_end().to_string();
+
+        let xrefs: Vec<_> = self.xrefs.get(&ip).into_iter().flatten()
+          .map(|x| json!({ "from": x.from, "kind": x.kind.to_string() }))
+          .collect();
+
+        let mut value = json!({
+            "addr": ip,
+            "size": stmt.size(),
+            "label": self.labels.get(&ip),
+            "text": text,
+            "comments": self.annotations(ip, opts),
+            "xrefs": xrefs,
+            "modified": self.is_modified(ip, stmt, opts),
+        });
+
+        let fields = match stmt {
+            AsmItem::Instruction(instr) => {
+                let operands: Vec<_> = instr.operands().iter().enumerate()
+                  .map(|(i, operand)| match operand {
+                      Operand::Src(SrcOperand::Immediate(val)) => {
+                          let word = self.word_at(ip + 1 + i, *val,
+                            false, opts);
+                          let symbol = match word {
+                              Word::Symbol(name) => Some(name),
+                              _ => None,
+                          };
+                          json!({ "kind": "immediate", "value": val,
+                            "symbol": symbol })
+                      },
+                      Operand::Src(SrcOperand::Register(r)) =>
+                        json!({ "kind": "register", "register": r }),
+                      Operand::Dst(dst) => json!({ "kind": "register",
+                        "register": dst.register() }),
+                  })
+                  .collect();
+                let target = match instr.target() {
+                    Some(SrcOperand::Immediate(addr)) => Some(addr),
+                    _ => None,
+                };
+                json!({
+                    "kind": "instruction",
+                    "opcode": instr.opcode(),
+                    "mnemonic": instr.info().mnemonic,
+                    "operands": operands,
+                    "target": target,
+                })
+            },
+
+            AsmItem::Value(word) => json!({ "kind": "word", "value": word }),
+
+            AsmItem::String(kind, chars) => json!({
+                "kind": "string",
+                "directive": kind.directive(),
+                "value": escape_string(chars),
+                "words": chars,
+            }),
+        };
+
+        if let (Value::Object(value), Value::Object(fields)) =
+          (&mut value, fields) {
+            value.extend(fields);
+        }
+        Ok(value)
+    }
+
+    pub fn write_json<W: Write>(&self, w: &mut W, opts: &DisAsmOpts
+      ) -> Result<(), DisAsmError> {
+        serde_json::to_writer_pretty(&mut *w, &self.to_json(opts)?)
+          .map_err(io::Error::from)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    pub fn write_html<W: Write>(&self, w: &mut W, title: &str,
+      opts: &DisAsmOpts) -> Result<(), DisAsmError> {
+        writeln!(w, "<!DOCTYPE html>")?;
+        writeln!(w, "<html><head><meta charset=\"utf-8\">")?;
+        writeln!(w, "<title>{}</title>", escape_html(title))?;
+        writeln!(w, "<style>{}</style>", HTML_STYLE)?;
+        writeln!(w, "</head><body><pre>")?;
+
+        for (ip, stmt) in &self.stmts {
+            for line in self.annotations(*ip, opts) {
+                writeln!(w, "<span class=\"comment\">; {}</span>",
+                  escape_html(&line))?;
+            }
+            if opts.xrefs && self.labels.contains_key(ip) {
+                self.write_html_xrefs(w, *ip)?;
+            }
+
+            let class = if self.is_modified(*ip, stmt, opts) {
+                "line modified"
+            } else {
+                "line"
+            };
+            write!(w, "<span class=\"{}\" id=\"a{}\">", class, ip)?;
+            // anchors for addresses inside the statement, so every address
+            //   can be linked to
+            for addr in ip + 1..ip + stmt.size() {
+                write!(w, "<span id=\"a{}\"></span>", addr)?;
+            }
+            write!(w, "<a class=\"addr\" href=\"#a{0}\">{0:5}</a>  ", ip)?;
+            if let Some(label) = self.labels.get(ip) {
+                write!(w, "<span class=\"label\">{}:</span> ",
+                  escape_html(label))?;
+            }
+            self.write_html_stmt(w, *ip, stmt, opts)?;
+            writeln!(w, "</span>")?;
+        }
+
+        writeln!(w, "</pre></body></html>")?;
+        Ok(())
+    }
+
+    fn write_html_xrefs<W: Write>(&self, w: &mut W, addr: usize
+      ) -> Result<(), DisAsmError> {
+        if let Some(refs) = self.xrefs.get(&addr) {
+            for chunk in refs.chunks(4) {
+                let descs: Vec<_> = chunk.iter()
+                  .map(|x| format!("{} from <a href=\"#a{}\">{}</a>", x.kind,
+                    x.from, escape_html(&self.describe_addr(x.from))))
+                  .collect();
+                writeln!(w, "<span class=\"comment\">; xref: {}</span>",
+                  descs.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_html_stmt<W: Write>(&self, w: &mut W, ip: usize, stmt: &AsmItem,
+      opts: &DisAsmOpts) -> Result<(), DisAsmError> {
+        match stmt {
+            AsmItem::Instruction(instr) => {
+                let style = &opts.style;
+                write!(w, "<span class=\"mn\">{}</span>",
+                  style.mnemonic(instr.info().mnemonic))?;
+                let target = instr.info().target;
+                let in_out = matches!(instr, Instruction::Out(_));
+                for (i, operand) in instr.operands().iter().enumerate() {
+                    write!(w, "{}", if i == 0 { " " } else { ", " })?;
+                    match operand {
+                        Operand::Src(SrcOperand::Immediate(val)) =>
+                          self.write_html_word(w, ip + 1 + i, *val,
+                            target == Some(i), in_out, opts)?,
+                        Operand::Src(SrcOperand::Register(r)) =>
+                          write!(w, "<span class=\"reg\">{}</span>",
+                            style.register(*r))?,
+                        Operand::Dst(dst) =>
+                          write!(w, "<span class=\"reg\">{}</span>",
+                            style.register(dst.register()))?,
+                    };
+                }
+            },
+
+            AsmItem::Value(word) =>
+              self.write_html_word(w, ip, *word, false, false, opts)?,
+
+            AsmItem::String(kind, chars) => {
+                write!(w, "<span class=\"mn\">{}</span> ", kind.directive())?;
+                write!(w, "<span class=\"str\">\"{}\"</span>",
+                  escape_html(&escape_string(chars)))?;
+            },
+        };
+        Ok(())
+    }
+
+    fn write_html_word<W: Write>(&self, w: &mut W, at: usize, value: u16,
+      is_target: bool, in_out: bool, opts: &DisAsmOpts
+      ) -> Result<(), DisAsmError> {
+        let end = self.stmts.last().map_or(0, |(ip, stmt)| ip + stmt.size());
+        let linkable = (value as usize) < end;
+        match self.word_at(at, value, in_out, opts) {
+            Word::Symbol(name) if linkable =>
+              write!(w, "<a class=\"sym\" href=\"#a{}\">{}</a>",
+                value, escape_html(name))?,
+            Word::Symbol(name) =>
+              write!(w, "<span class=\"sym\">{}</span>", escape_html(name))?,
+            Word::Number(n) if is_target && linkable =>
+              write!(w, "<a class=\"num\" href=\"#a{}\">{}</a>",
+                n, opts.style.number(n))?,
+            Word::Number(n) =>
+              write!(w, "<span class=\"num\">{}</span>", opts.style.number(n))?,
+            Word::Char(c) =>
+              write!(w, "<span class=\"chr\">'{}'</span>",
+                escape_html(&c.to_string()))?,
+        };
+        Ok(())
+    }
+}
+
+const HTML_STYLE: &str = "
+body { background: #1e1e1e; color: #d4d4d4; }
+pre { font-family: monospace; }
+a { color: inherit; text-decoration: none; }
+a:hover { text-decoration: underline; }
+.line:target, .line:target * { background: #264f78; }
+.modified { background: #3b2e1a; }
+.addr { color: #6e6e6e; }
+.label { color: #dcdcaa; font-weight: bold; }
+.mn { color: #569cd6; }
+.reg { color: #9cdcfe; }
+.num { color: #b5cea8; }
+.chr, .str { color: #ce9178; }
+.sym { color: #4ec9b0; }
+.comment { color: #6a9955; }
+";
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,359 @@
+use std::{
+    convert::TryFrom,
+    error,
+    fmt,
+    iter::Peekable,
+    str::CharIndices,
+};
+
+use super::{
+    asm::{Labels, parse_register},
+    vm::Vm,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExprError {
+    SyntaxError(String),
+    UnknownLabel(String),
+    BadAddress(i64),
+    EmptyStack,
+    DivideByZero,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprError::SyntaxError(msg) => write!(f, "syntax error: {}", msg),
+            ExprError::UnknownLabel(lbl) =>
+              write!(f, "unknown label: \"{}\"", lbl),
+            ExprError::BadAddress(addr) =>
+              write!(f, "address out of range: {}", addr),
+            ExprError::EmptyStack => write!(f, "empty stack"),
+            ExprError::DivideByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+impl error::Error for ExprError { }
+
+pub type Result<T> = std::result::Result<T, ExprError>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+// values are evaluated as wide signed integers, so that arithmetic in
+//   conditions behaves like arithmetic rather than like the VM's
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    Register(usize),
+    Ip,
+    // the number of values on the stack
+    StackDepth,
+    StackTop,
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+// loosest-binding first
+const PRECEDENCE: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne), ("<=", BinaryOp::Le),
+      (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Mod)],
+];
+
+const OPERATORS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~",
+    "(", ")", "[", "]",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl Expr {
+    // labels are resolved to addresses as the expression is parsed
+    pub fn parse(source: &str, labels: &Labels) -> Result<Expr> {
+        let toks = tokenize(source)?;
+        let mut parser = Parser { toks: &toks, pos: 0, labels };
+        let expr = parser.binary(0)?;
+        match parser.toks.get(parser.pos) {
+            None => Ok(expr),
+            Some(tok) => Err(ExprError::SyntaxError(
+              format!("unexpected {:?}", tok))),
+        }
+    }
+
+    pub fn eval(&self, vm: &Vm) -> Result<i64> {
+        let val = match self {
+            Expr::Number(n) => *n,
+            Expr::Register(r) => vm.registers()[*r] as i64,
+            Expr::Ip => vm.ip() as i64,
+            Expr::StackDepth => vm.stack().len() as i64,
+            Expr::StackTop => *vm.stack().last()
+              .ok_or(ExprError::EmptyStack)? as i64,
+
+            Expr::Memory(addr) => {
+                let addr = addr.eval(vm)?;
+                let word = usize::try_from(addr).ok()
+                  .and_then(|a| vm.memory().get(a))
+                  .ok_or(ExprError::BadAddress(addr))?;
+                *word as i64
+            },
+
+            Expr::Unary(op, e) => {
+                let v = e.eval(vm)?;
+                match op {
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::Not => (v == 0) as i64,
+                    UnaryOp::BitNot => !v,
+                }
+            },
+
+            // short-circuit, so `sp > 0 && top == 5` is safe
+            Expr::Binary(BinaryOp::Or, a, b) =>
+              (a.eval(vm)? != 0 || b.eval(vm)? != 0) as i64,
+            Expr::Binary(BinaryOp::And, a, b) =>
+              (a.eval(vm)? != 0 && b.eval(vm)? != 0) as i64,
+
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(vm)?, b.eval(vm)?);
+                match op {
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Shl => a.wrapping_shl(b as u32),
+                    BinaryOp::Shr => a.wrapping_shr(b as u32),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div => a.checked_div(b)
+                      .ok_or(ExprError::DivideByZero)?,
+                    BinaryOp::Mod => a.checked_rem(b)
+                      .ok_or(ExprError::DivideByZero)?,
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            },
+        };
+        Ok(val)
+    }
+
+    #[inline]
+    pub fn is_true(&self, vm: &Vm) -> Result<bool> {
+        Ok(self.eval(vm)? != 0)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut toks = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            toks.push(Token::Number(lex_number(&mut chars, source)?));
+        } else if c == '\'' {
+            chars.next();
+            let lit = chars.next().map(|(_, c)| c as i64);
+            match (lit, chars.next()) {
+                (Some(lit), Some((_, '\''))) => toks.push(Token::Number(lit)),
+                _ => return Err(ExprError::SyntaxError(
+                  "bad character literal".to_string())),
+            };
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let mut ident = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                    ident.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            toks.push(Token::Ident(ident));
+        } else {
+            let op = OPERATORS.iter()
+              .find(|op| source[i..].starts_with(*op))
+              .ok_or_else(|| ExprError::SyntaxError(
+                format!("unexpected character '{}'", c)))?;
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            toks.push(Token::Op(op));
+        }
+    }
+    Ok(toks)
+}
+
+fn lex_number(chars: &mut Peekable<CharIndices<'_>>, source: &str
+  ) -> Result<i64> {
+    let start = chars.peek().map_or(source.len(), |&(i, _)| i);
+    let mut end = start;
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_ascii_alphanumeric() {
+            end = i + c.len_utf8();
+            chars.next();
+        } else {
+            break;
+        }
+    }
+
+    let text = &source[start..end];
+    let (digits, radix) = match text.get(..2) {
+        Some("0x") | Some("0X") => (&text[2..], 16),
+        Some("0o") | Some("0O") => (&text[2..], 8),
+        Some("0b") | Some("0B") => (&text[2..], 2),
+        _ => (text, 10),
+    };
+    i64::from_str_radix(digits, radix).map_err(|_|
+      ExprError::SyntaxError(format!("bad number \"{}\"", text)))
+}
+
+struct Parser<'a> {
+    toks: &'a [Token],
+    pos: usize,
+    labels: &'a Labels,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let tok = self.toks.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.toks.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        match self.next() {
+            Some(Token::Op(o)) if *o == op => Ok(()),
+            _ => Err(ExprError::SyntaxError(format!("expected '{}'", op))),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op() {
+            let op = match PRECEDENCE[level].iter().find(|(o, _)| *o == op) {
+                Some((_, op)) => *op,
+                None => break,
+            };
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let op = match self.peek_op() {
+            Some("-") => UnaryOp::Neg,
+            Some("!") => UnaryOp::Not,
+            Some("~") => UnaryOp::BitNot,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(*n)),
+
+            Some(Token::Op("(")) => {
+                let expr = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            },
+
+            Some(Token::Op("[")) => self.memory(),
+
+            Some(Token::Ident(name)) if name == "mem" => {
+                self.expect("[")?;
+                self.memory()
+            },
+
+            Some(Token::Ident(name)) => match name.as_str() {
+                "ip" => Ok(Expr::Ip),
+                "sp" => Ok(Expr::StackDepth),
+                "top" => Ok(Expr::StackTop),
+                _ => match parse_register(name) {
+                    Some(r) => Ok(Expr::Register(r)),
+                    None => self.label(name),
+                },
+            },
+
+            Some(tok) => Err(ExprError::SyntaxError(
+              format!("unexpected {:?}", tok))),
+            None => Err(ExprError::SyntaxError(
+              "unexpected end of expression".to_string())),
+        }
+    }
+
+    // after the opening bracket
+    fn memory(&mut self) -> Result<Expr> {
+        let addr = self.binary(0)?;
+        self.expect("]")?;
+        Ok(Expr::Memory(Box::new(addr)))
+    }
+
+    fn label(&self, name: &str) -> Result<Expr> {
+        self.labels.iter()
+          .find(|(_, v)| v.as_str() == name)
+          .map(|(addr, _)| Expr::Number(*addr as i64))
+          .ok_or_else(|| ExprError::UnknownLabel(name.to_string()))
+    }
+}
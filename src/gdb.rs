@@ -0,0 +1,622 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    error,
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+use super::vm::{self, Vm, VmState};
+
+// gdb addresses bytes, and the machine words; a word address `n` is byte
+//   address `2 * n`, with words stored little-endian. the program counter
+//   is reported as a byte address, like everything else gdb sees
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.synacor.core">
+    <reg name="r0" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="r1" bitsize="16" type="uint16"/>
+    <reg name="r2" bitsize="16" type="uint16"/>
+    <reg name="r3" bitsize="16" type="uint16"/>
+    <reg name="r4" bitsize="16" type="uint16"/>
+    <reg name="r5" bitsize="16" type="uint16"/>
+    <reg name="r6" bitsize="16" type="uint16"/>
+    <reg name="r7" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const NUM_REGISTERS: usize = 9;
+const PC_REGISTER: usize = 8;
+
+// how often a running target checks for an interrupt from the debugger
+const POLL_INTERVAL: usize = 1024;
+// which the debugger sends outside of any packet
+const INTERRUPT: u8 = 0x03;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+#[derive(Debug)]
+pub enum GdbError {
+    IOError(io::Error),
+    // the debugger hung up
+    Disconnected,
+}
+
+impl From<io::Error> for GdbError {
+    fn from(other: io::Error) -> Self {
+        GdbError::IOError(other)
+    }
+}
+
+impl fmt::Display for GdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GdbError::IOError(e) => write!(f, "I/O error: {}", e),
+            GdbError::Disconnected => write!(f, "debugger disconnected"),
+        }
+    }
+}
+
+impl error::Error for GdbError { }
+
+pub type Result<T> = std::result::Result<T, GdbError>;
+
+// a link to the debugger, which can be checked while the target runs
+//   for an interrupt, without waiting on it
+pub trait Connection: Read + Write {
+    // a byte that's already arrived, if any
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+}
+
+impl Connection for TcpStream {
+    fn poll_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0u8];
+        self.set_nonblocking(true)?;
+        let res = self.read(&mut buf);
+        self.set_nonblocking(false)?;
+        match res {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(Some(buf[0])),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+// the protocol over our own stdin and stdout; these can't be interrupted
+pub struct Stdio {
+    stdin: io::Stdin,
+    stdout: io::Stdout,
+}
+
+impl Stdio {
+    pub fn new() -> Self {
+        Self { stdin: io::stdin(), stdout: io::stdout() }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Read for Stdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdin.read(buf)
+    }
+}
+
+impl Write for Stdio {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl Connection for Stdio { }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+impl WatchKind {
+    fn stop_name(&self) -> &'static str {
+        match self {
+            WatchKind::Write => "watch",
+            WatchKind::Read => "rwatch",
+            WatchKind::Access => "awatch",
+        }
+    }
+}
+
+// why the target stopped, as far as the debugger's concerned
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Stop {
+    Signal(u8),
+    Watch(WatchKind, usize),
+    Halted,
+}
+
+pub struct GdbServer {
+    vm: Vm,
+    // word addresses
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<(usize, WatchKind)>,
+    no_ack: bool,
+    halted: bool,
+    // whether the program's output goes to the debugger, in `O` packets
+    console: bool,
+    // what arrived while the target ran that wasn't an interrupt
+    unread: VecDeque<u8>,
+}
+
+impl GdbServer {
+    pub fn new(vm: Vm) -> Self {
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            no_ack: false,
+            halted: false,
+            console: false,
+            unread: VecDeque::new(),
+        }
+    }
+
+    #[inline]
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    // answers requests until the debugger kills the target or detaches;
+    //   `input` and `output` are the program's own
+    pub fn serve<C: Connection, R: Read, W: Write>(&mut self, conn: &mut C,
+      input: &mut R, output: &mut W) -> Result<()> {
+        loop {
+            let packet = read_packet(conn, &mut self.unread, self.no_ack)?;
+            let packet = match packet {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            match packet.as_str() {
+                "k" => return Ok(()),
+                "D" => {
+                    write_packet(conn, "OK", &mut self.unread, self.no_ack)?;
+                    return Ok(());
+                },
+                _ => { },
+            };
+
+            let reply = self.handle(&packet, conn, input, output)?;
+            write_packet(conn, &reply, &mut self.unread, self.no_ack)?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+    }
+
+    // like `serve`, but the program's output is shown by the debugger
+    pub fn serve_console<C: Connection, R: Read>(&mut self, conn: &mut C,
+      input: &mut R) -> Result<()> {
+        self.console = true;
+        self.serve(conn, input, &mut io::sink())
+    }
+
+    fn handle<C: Connection, R: Read, W: Write>(&mut self, packet: &str,
+      conn: &mut C, input: &mut R, output: &mut W) -> Result<String> {
+        let cmd = packet.get(..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+        let reply = match cmd {
+            "?" => stop_reply(if self.halted {
+                Stop::Halted
+            } else {
+                Stop::Signal(SIGTRAP)
+            }),
+
+            "g" => (0..NUM_REGISTERS)
+              .map(|r| hex_word(self.read_register(r)))
+              .collect(),
+
+            "G" => match parse_words(args) {
+                Some(words) if words.len() == NUM_REGISTERS => {
+                    for (r, word) in words.into_iter().enumerate() {
+                        self.write_register(r, word);
+                    }
+                    "OK".to_string()
+                },
+                _ => "E01".to_string(),
+            },
+
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(r) if r < NUM_REGISTERS => hex_word(self.read_register(r)),
+                _ => "E01".to_string(),
+            },
+
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let r = parts.next()
+                  .and_then(|r| usize::from_str_radix(r, 16).ok());
+                let word = parts.next().and_then(parse_words);
+                match (r, word.as_deref()) {
+                    (Some(r), Some(&[word])) if r < NUM_REGISTERS => {
+                        self.write_register(r, word);
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+
+            "m" => {
+                let bytes = parse_range(args)
+                  .and_then(|(addr, len)| self.read_bytes(addr, len));
+                match bytes {
+                    Some(bytes) =>
+                      bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+                    None => "E01".to_string(),
+                }
+            },
+
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let bytes = parts.next().and_then(parse_bytes);
+                match (range, bytes) {
+                    (Some((addr, len)), Some(bytes)) if bytes.len() == len
+                      && self.write_bytes(addr, &bytes) => "OK".to_string(),
+                    _ => "E01".to_string(),
+                }
+            },
+
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.vm.jump_to(addr / 2);
+                }
+                let stop = self.resume(cmd == "s", conn, input, output)?;
+                output.flush()?;
+                stop_reply(stop)
+            },
+
+            "Z" | "z" => match self.set_point(args, cmd == "Z") {
+                Some(true) => "OK".to_string(),
+                Some(false) => String::new(),
+                None => "E01".to_string(),
+            },
+
+            "q" | "Q" => self.query(packet),
+
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        const FEATURES: &str = "qXfer:features:read:target.xml:";
+        if let Some(range) = packet.strip_prefix(FEATURES) {
+            return match parse_range(range) {
+                Some((offset, len)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = offset.min(xml.len());
+                    let end = xml.len().min(start + len);
+                    let more = if end < xml.len() { "m" } else { "l" };
+                    format!("{}{}", more,
+                      String::from_utf8_lossy(&xml[start..end]))
+                },
+                None => "E01".to_string(),
+            };
+        }
+
+        match packet.split(':').next().unwrap_or("") {
+            "qSupported" =>
+              "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+"
+              .to_string(),
+            "QStartNoAckMode" => "OK".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn read_register(&self, r: usize) -> u16 {
+        match r {
+            PC_REGISTER => (self.vm.ip() * 2) as u16,
+            r => self.vm.registers()[r],
+        }
+    }
+
+    fn write_register(&mut self, r: usize, word: u16) {
+        match r {
+            PC_REGISTER => self.vm.jump_to(word as usize / 2),
+            r => self.vm.registers_mut()[r] = word,
+        };
+    }
+
+    fn read_bytes(&self, addr: usize, len: usize) -> Option<Vec<u8>> {
+        let memory = self.vm.memory();
+        (addr..addr.checked_add(len)?)
+          .map(|a| memory.get(a / 2).map(|w| w.to_le_bytes()[a % 2]))
+          .collect()
+    }
+
+    fn write_bytes(&mut self, addr: usize, bytes: &[u8]) -> bool {
+        if (addr + bytes.len()).div_ceil(2) > self.vm.memory().len() {
+            return false;
+        }
+        let memory = self.vm.memory_mut();
+        for (a, &byte) in (addr..).zip(bytes) {
+            let mut word = memory[a / 2].to_le_bytes();
+            word[a % 2] = byte;
+            memory[a / 2] = u16::from_le_bytes(word);
+        }
+        true
+    }
+
+    // `Some(false)` for the kinds we don't support
+    fn set_point(&mut self, args: &str, insert: bool) -> Option<bool> {
+        let mut parts = args.split(',');
+        let kind = parts.next()?;
+        let addr = parse_hex(parts.next()?)? / 2;
+        if addr >= self.vm.memory().len() {
+            return None;
+        }
+
+        let watch = match kind {
+            // software and hardware breakpoints are all the same to us
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                return Some(true);
+            },
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(false),
+        };
+        if insert {
+            self.watchpoints.insert((addr, watch));
+        } else {
+            self.watchpoints.remove(&(addr, watch));
+        }
+        Some(true)
+    }
+
+    // the watchpoint the next instruction will trigger, if any
+    fn watch_hit(&self) -> Option<(WatchKind, usize)> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+
+        let (_, instr) = self.vm.decode_next().ok()?;
+        let fx = instr.effects(self.vm.ip()).resolve(&self.vm);
+        self.watchpoints.iter()
+          .find(|(addr, kind)| {
+              let read = fx.mem_read == Some(*addr as u16);
+              let write = fx.mem_write == Some(*addr as u16);
+              match kind {
+                  WatchKind::Write => write,
+                  WatchKind::Read => read,
+                  WatchKind::Access => read || write,
+              }
+          })
+          .map(|(addr, kind)| (*kind, *addr))
+    }
+
+    fn resume<C: Connection, R: Read, W: Write>(&mut self, single_step: bool,
+      conn: &mut C, input: &mut R, output: &mut W) -> Result<Stop> {
+        let mut console = Vec::new();
+        let stop = self.run(single_step, conn, input, output, &mut console)?;
+        self.send_console(conn, &mut console)?;
+        Ok(stop)
+    }
+
+    // program output can only be sent while the target's running, so
+    //   anything left is sent before the stop reply
+    fn send_console<C: Connection>(&mut self, conn: &mut C,
+      console: &mut Vec<u8>) -> Result<()> {
+        if console.is_empty() {
+            return Ok(());
+        }
+        let hex: String = console.iter().map(|b| format!("{:02x}", b))
+          .collect();
+        console.clear();
+        write_packet(conn, &format!("O{}", hex), &mut self.unread, self.no_ack)
+    }
+
+    fn run<C: Connection, R: Read, W: Write>(&mut self, single_step: bool,
+      conn: &mut C, input: &mut R, output: &mut W, console: &mut Vec<u8>
+      ) -> Result<Stop> {
+        if self.halted {
+            return Ok(Stop::Halted);
+        }
+
+        // the first instruction runs even from a breakpoint, or we'd
+        //   never get past one
+        let mut steps = 0;
+        loop {
+            let watch = self.watch_hit();
+            let state = if self.console {
+                self.vm.step(input, console)
+            } else {
+                self.vm.step(input, output)
+            };
+            match state {
+                Ok(VmState::Running) => { },
+                Ok(VmState::Halted) => {
+                    self.halted = true;
+                    return Ok(Stop::Halted);
+                },
+                // out of input; the `in` can be retried once there's more
+                Err(vm::Error::IOError) => return Ok(Stop::Signal(SIGINT)),
+                Err(_) => return Ok(Stop::Signal(SIGILL)),
+            };
+
+            if let Some((kind, addr)) = watch {
+                return Ok(Stop::Watch(kind, addr));
+            }
+            if single_step || self.breakpoints.contains(&self.vm.ip()) {
+                return Ok(Stop::Signal(SIGTRAP));
+            }
+
+            if console.ends_with(b"\n") {
+                self.send_console(conn, console)?;
+            }
+
+            steps += 1;
+            if steps % POLL_INTERVAL == 0 {
+                // one may have come in while we waited on an ack
+                let pending = self.unread.iter().position(|&b| b == INTERRUPT);
+                if let Some(i) = pending {
+                    self.unread.remove(i);
+                    return Ok(Stop::Signal(SIGINT));
+                }
+                while let Some(byte) = conn.poll_byte()? {
+                    if byte == INTERRUPT {
+                        return Ok(Stop::Signal(SIGINT));
+                    }
+                    self.unread.push_back(byte);
+                }
+            }
+        }
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Signal(sig) => format!("S{:02x}", sig),
+        Stop::Watch(kind, addr) =>
+          format!("T{:02x}{}:{:x};", SIGTRAP, kind.stop_name(), addr * 2),
+        Stop::Halted => "W00".to_string(),
+    }
+}
+
+// `None` for acknowledgements, interrupts while stopped, and packets
+//   which arrived damaged and will be resent
+fn read_packet<C: Connection>(conn: &mut C, unread: &mut VecDeque<u8>,
+  no_ack: bool) -> Result<Option<String>> {
+    let mut byte = [0u8];
+    let mut next = |conn: &mut C| -> Result<u8> {
+        if let Some(b) = unread.pop_front() {
+            return Ok(b);
+        }
+        match conn.read(&mut byte)? {
+            0 => Err(GdbError::Disconnected),
+            _ => Ok(byte[0]),
+        }
+    };
+
+    if next(conn)? != b'$' {
+        return Ok(None);
+    }
+
+    let mut data = Vec::new();
+    loop {
+        match next(conn)? {
+            b'#' => break,
+            b'}' => data.push(next(conn)? ^ 0x20),
+            b => data.push(b),
+        };
+    }
+    let checksum = [next(conn)?, next(conn)?];
+    let checksum = std::str::from_utf8(&checksum).ok()
+      .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+    let expected = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    if !no_ack {
+        let ack = if checksum == Some(expected) { b"+" } else { b"-" };
+        conn.write_all(ack)?;
+        conn.flush()?;
+    }
+    if checksum != Some(expected) && !no_ack {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&data).into_owned()))
+}
+
+// anything but an ack that arrives meanwhile is kept in `unread`
+fn write_packet<C: Connection>(conn: &mut C, data: &str,
+  unread: &mut VecDeque<u8>, no_ack: bool) -> Result<()> {
+    // in one write, so it goes out in one segment
+    let mut frame = vec![b'$'];
+    for &b in data.as_bytes() {
+        match b {
+            b'$' | b'#' | b'}' | b'*' => frame.extend([b'}', b ^ 0x20]),
+            b => frame.push(b),
+        };
+    }
+    let checksum = frame[1..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    write!(frame, "#{:02x}", checksum)?;
+
+    loop {
+        conn.write_all(&frame)?;
+        conn.flush()?;
+        if no_ack {
+            return Ok(());
+        }
+
+        // resend until it's acknowledged
+        let mut ack = [0u8];
+        loop {
+            match conn.read(&mut ack)? {
+                0 => return Err(GdbError::Disconnected),
+                _ if ack[0] == b'+' => return Ok(()),
+                _ if ack[0] == b'-' => break,
+                _ => unread.push_back(ack[0]),
+            };
+        }
+    }
+}
+
+#[inline]
+fn hex_word(word: u16) -> String {
+    word.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[inline]
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+// `addr,length`
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let mut parts = s.splitn(2, ',');
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2)
+      .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+      .collect()
+}
+
+fn parse_words(s: &str) -> Option<Vec<u16>> {
+    let bytes = parse_bytes(s)?;
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(bytes.chunks(2).map(|w| u16::from_le_bytes([w[0], w[1]])).collect())
+}
@@ -0,0 +1,222 @@
+use super::vm::{
+    Error,
+    Result,
+    Instruction,
+    SrcOperand,
+    DstOperand,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OperandKind {
+    Src,
+    Dst,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlowClass {
+    // always continues with the next instruction
+    Sequential,
+    Jump,
+    // jumps or falls through, depending on a condition
+    Branch,
+    Call,
+    Return,
+    Halt,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct SideEffects {
+    pub reads_memory: bool,
+    pub writes_memory: bool,
+    pub pushes: bool,
+    pub pops: bool,
+    pub input: bool,
+    pub output: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpInfo {
+    pub opcode: u16,
+    pub mnemonic: &'static str,
+    pub operands: &'static [OperandKind],
+    pub flow: FlowClass,
+    // index of the operand holding the jump or call target
+    pub target: Option<usize>,
+    pub effects: SideEffects,
+}
+
+impl OpInfo {
+    #[inline]
+    pub fn size(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    #[inline]
+    pub fn is_branch(&self) -> bool {
+        matches!(self.flow, FlowClass::Jump | FlowClass::Branch)
+    }
+
+    #[inline]
+    pub fn is_conditional(&self) -> bool {
+        self.flow == FlowClass::Branch
+    }
+
+    #[inline]
+    pub fn transfers_control(&self) -> bool {
+        self.flow != FlowClass::Sequential
+    }
+
+    // can execution continue at the next instruction (possibly after a
+    //   return, for calls)?
+    #[inline]
+    pub fn falls_through(&self) -> bool {
+        matches!(self.flow,
+          FlowClass::Sequential | FlowClass::Branch | FlowClass::Call)
+    }
+}
+
+const NONE: SideEffects = SideEffects {
+    reads_memory: false,
+    writes_memory: false,
+    pushes: false,
+    pops: false,
+    input: false,
+    output: false,
+};
+
+// the one table of instructions, in opcode order: a row's position is its
+//   opcode, and its operands name the fields of its `Instruction` variant
+macro_rules! opcodes {
+    ($($variant:ident $(($($arg:ident: $kind:ident),+))? {
+        $mnemonic:literal, $flow:ident, target: $target:expr,
+        effects: $effects:expr $(,)?
+    }),+ $(,)?) => {
+        #[derive(Copy, Clone)]
+        #[repr(u16)]
+        enum Opcode { $($variant),+ }
+
+        pub const OPCODES: [OpInfo; [$(Opcode::$variant),+].len()] = [$(
+            OpInfo {
+                opcode: Opcode::$variant as u16,
+                mnemonic: $mnemonic,
+                operands: &[$($(OperandKind::$kind),+)?],
+                flow: FlowClass::$flow,
+                target: $target,
+                effects: $effects,
+            }
+        ),+];
+
+        impl Instruction {
+            #[inline]
+            pub fn opcode(&self) -> u16 {
+                match self {
+                    $(Instruction::$variant { .. } => Opcode::$variant as u16),+
+                }
+            }
+
+            pub fn operands(&self) -> Vec<Operand> {
+                match *self {
+                    $(Instruction::$variant $(($($arg),+))? =>
+                      vec![$($(Operand::$kind($arg)),+)?]),+
+                }
+            }
+
+            pub fn from_operands(opcode: u16, operands: &[Operand]
+              ) -> Result<Instruction> {
+                $(if opcode == Opcode::$variant as u16 {
+                    if let [$($(Operand::$kind($arg)),+)?] = *operands {
+                        return Ok(Instruction::$variant $(($($arg),+))?);
+                    }
+                })+
+                Err(Error::IllegalInstruction(opcode))
+            }
+        }
+    };
+}
+
+opcodes! {
+    Halt { "halt", Halt, target: None, effects: NONE },
+    Set(a: Dst, b: Src) { "set", Sequential, target: None, effects: NONE },
+    Push(a: Src) { "push", Sequential, target: None,
+      effects: SideEffects { pushes: true, ..NONE } },
+    Pop(a: Dst) { "pop", Sequential, target: None,
+      effects: SideEffects { pops: true, ..NONE } },
+    Eq(a: Dst, b: Src, c: Src) { "eq", Sequential, target: None,
+      effects: NONE },
+    Gt(a: Dst, b: Src, c: Src) { "gt", Sequential, target: None,
+      effects: NONE },
+    Jmp(a: Src) { "jmp", Jump, target: Some(0), effects: NONE },
+    Jt(a: Src, b: Src) { "jt", Branch, target: Some(1), effects: NONE },
+    Jf(a: Src, b: Src) { "jf", Branch, target: Some(1), effects: NONE },
+    Add(a: Dst, b: Src, c: Src) { "add", Sequential, target: None,
+      effects: NONE },
+    Mult(a: Dst, b: Src, c: Src) { "mult", Sequential, target: None,
+      effects: NONE },
+    Mod(a: Dst, b: Src, c: Src) { "mod", Sequential, target: None,
+      effects: NONE },
+    And(a: Dst, b: Src, c: Src) { "and", Sequential, target: None,
+      effects: NONE },
+    Or(a: Dst, b: Src, c: Src) { "or", Sequential, target: None,
+      effects: NONE },
+    Not(a: Dst, b: Src) { "not", Sequential, target: None, effects: NONE },
+    Rmem(a: Dst, b: Src) { "rmem", Sequential, target: None,
+      effects: SideEffects { reads_memory: true, ..NONE } },
+    Wmem(a: Src, b: Src) { "wmem", Sequential, target: None,
+      effects: SideEffects { writes_memory: true, ..NONE } },
+    Call(a: Src) { "call", Call, target: Some(0),
+      effects: SideEffects { pushes: true, ..NONE } },
+    Ret { "ret", Return, target: None,
+      effects: SideEffects { pops: true, ..NONE } },
+    Out(a: Src) { "out", Sequential, target: None,
+      effects: SideEffects { output: true, ..NONE } },
+    In(a: Dst) { "in", Sequential, target: None,
+      effects: SideEffects { input: true, ..NONE } },
+    Noop { "noop", Sequential, target: None, effects: NONE },
+}
+
+#[inline]
+pub fn by_opcode(opcode: u16) -> Option<&'static OpInfo> {
+    OPCODES.get(opcode as usize)
+}
+
+pub fn by_mnemonic(mnemonic: &str) -> Option<&'static OpInfo> {
+    OPCODES.iter().find(|info| info.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Src(SrcOperand),
+    Dst(DstOperand),
+}
+
+impl Operand {
+    pub fn decode(kind: OperandKind, word: u16) -> Result<Self> {
+        match kind {
+            OperandKind::Src => Ok(Operand::Src(SrcOperand::decode(word)?)),
+            OperandKind::Dst => Ok(Operand::Dst(DstOperand::decode(word)?)),
+        }
+    }
+
+    pub fn encode(&self) -> Result<u16> {
+        match self {
+            Operand::Src(src) => src.encode(),
+            Operand::Dst(dst) => dst.encode(),
+        }
+    }
+}
+
+impl Instruction {
+    #[inline]
+    pub fn info(&self) -> &'static OpInfo {
+        &OPCODES[self.opcode() as usize]
+    }
+
+    // the jump or call target operand, if any
+    #[inline]
+    pub fn target(&self) -> Option<SrcOperand> {
+        match self.operands().get(self.info().target?) {
+            Some(Operand::Src(src)) => Some(*src),
+            _ => None,
+        }
+    }
+}
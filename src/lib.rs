@@ -1,3 +1,12 @@
 pub mod vm;
+pub mod isa;
+pub mod effects;
 pub mod binary;
 pub mod asm;
+pub mod map;
+pub mod export;
+pub mod unpack;
+pub mod assembler;
+pub mod expr;
+pub mod gdb;
+pub mod dap;
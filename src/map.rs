@@ -0,0 +1,290 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{self, Debug},
+    io::{self, BufRead, Write},
+    ops::Range,
+};
+
+use super::asm::{
+    AsmError,
+    AsmItem,
+    ImageMap,
+    Labels,
+    StringHint,
+    StringHints,
+    StringKind,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    Data,
+    String,
+    Struct(String),
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionKind::Code => write!(f, "code"),
+            RegionKind::Data => write!(f, "data"),
+            RegionKind::String => write!(f, "string"),
+            RegionKind::Struct(name) => write!(f, "struct\t{}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub start: usize,
+    pub len: usize,
+    pub kind: RegionKind,
+}
+
+impl Region {
+    #[inline]
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.len
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: usize) -> bool {
+        self.range().contains(&addr)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub addr: usize,
+    pub field: &'static str,
+    pub ours: String,
+    pub theirs: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}: kept {} over {}",
+          self.field, self.addr, self.ours, self.theirs)
+    }
+}
+
+// one entry per line of comment text
+pub type Comments = HashMap<usize, Vec<String>>;
+pub type Signatures = HashMap<usize, String>;
+// where execution is known to start, which is always code
+pub type Entries = BTreeSet<usize>;
+
+// everything known about an image beyond its contents; on disk, one
+//   tab-separated `addr<TAB>what...` entry per line, where `what` is a
+//   label name or one of the dot directives below
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProjectMap {
+    pub labels: Labels,
+    pub string_hints: StringHints,
+    pub comments: Comments,
+    pub regions: Vec<Region>,
+    pub signatures: Signatures,
+    pub entries: Entries,
+}
+
+impl ProjectMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // what a disassembly worked out: its labels and the strings it found,
+    //   on top of this map
+    pub fn learn(&mut self, image: &ImageMap) {
+        for (&addr, name) in &image.labels {
+            self.labels.entry(addr).or_insert_with(|| name.clone());
+        }
+        for (ip, stmt) in &image.stmts {
+            if let AsmItem::String(kind, chars) = stmt {
+                let hint = match kind {
+                    StringKind::Prefixed => StringHint::Prefixed,
+                    StringKind::Raw => StringHint::Raw(chars.len()),
+                    StringKind::Out => continue,
+                };
+                self.string_hints.entry(*ip).or_insert(hint);
+            }
+        }
+    }
+
+    pub fn read<R: BufRead>(r: &mut R) -> Result<Self, AsmError> {
+        let mut map = ProjectMap::new();
+        for line in r.lines() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            map.read_entry(&line)
+              .ok_or_else(|| AsmError::LabelFileSyntaxError(line.to_string()))?;
+        }
+        Ok(map)
+    }
+
+    fn read_entry(&mut self, line: &str) -> Option<()> {
+        let mut iter = line.splitn(3, '\t');
+        let addr = iter.next()?.parse().ok()?;
+        let what = iter.next()?;
+        let rest = iter.next();
+        let mut fields = rest.unwrap_or("").split('\t');
+
+        match what {
+            ".str" => {
+                self.string_hints.insert(addr, StringHint::Prefixed);
+            },
+
+            ".ascii" => {
+                let len = fields.next()?.parse().ok().filter(|&len| len > 0)?;
+                self.string_hints.insert(addr, StringHint::Raw(len));
+            },
+
+            ".nostr" => {
+                self.string_hints.insert(addr, StringHint::Never);
+            },
+
+            ".comment" => {
+                self.comments.entry(addr).or_default()
+                  .push(rest.unwrap_or("").to_string());
+            },
+
+            ".region" => {
+                let len = fields.next()?.parse().ok()?;
+                let kind = match fields.next()? {
+                    "code" => RegionKind::Code,
+                    "data" => RegionKind::Data,
+                    "string" => RegionKind::String,
+                    "struct" => RegionKind::Struct(fields.next()?.to_string()),
+                    _ => return None,
+                };
+                self.regions.push(Region { start: addr, len, kind });
+            },
+
+            ".sig" => {
+                self.signatures.insert(addr, rest?.to_string());
+            },
+
+            ".entry" => {
+                self.entries.insert(addr);
+            },
+
+            _ if what.starts_with('.') => return None,
+
+            _ => {
+                self.labels.insert(addr, what.to_string());
+            },
+        };
+        Some(())
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let addrs: BTreeSet<usize> = self.labels.keys()
+          .chain(self.string_hints.keys())
+          .chain(self.comments.keys())
+          .chain(self.regions.iter().map(|r| &r.start))
+          .chain(self.signatures.keys())
+          .chain(self.entries.iter())
+          .copied()
+          .collect();
+
+        for addr in addrs {
+            if let Some(name) = self.labels.get(&addr) {
+                writeln!(w, "{}\t{}", addr, name)?;
+            }
+            if self.entries.contains(&addr) {
+                writeln!(w, "{}\t.entry", addr)?;
+            }
+            if let Some(sig) = self.signatures.get(&addr) {
+                writeln!(w, "{}\t.sig\t{}", addr, sig)?;
+            }
+            for region in self.regions.iter().filter(|r| r.start == addr) {
+                writeln!(w, "{}\t.region\t{}\t{}",
+                  addr, region.len, region.kind)?;
+            }
+            match self.string_hints.get(&addr) {
+                Some(StringHint::Prefixed) => writeln!(w, "{}\t.str", addr)?,
+                Some(StringHint::Raw(len)) =>
+                  writeln!(w, "{}\t.ascii\t{}", addr, len)?,
+                Some(StringHint::Never) => writeln!(w, "{}\t.nostr", addr)?,
+                None => { },
+            };
+            let comments = self.comments.get(&addr).into_iter().flatten();
+            for line in comments.flat_map(|text| text.lines()) {
+                writeln!(w, "{}\t.comment\t{}", addr, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    // combine two sets of changes made independently to `base`, keeping
+    //   ours wherever both sides changed the same thing differently
+    pub fn merge(base: &ProjectMap, ours: &ProjectMap, theirs: &ProjectMap
+      ) -> (ProjectMap, Vec<Conflict>) {
+        let mut conflicts = Vec::new();
+        let regions = |map: &ProjectMap| {
+            let mut by_start: HashMap<usize, Vec<Region>> = HashMap::new();
+            for region in &map.regions {
+                by_start.entry(region.start).or_default().push(region.clone());
+            }
+            by_start
+        };
+        let entries = |map: &ProjectMap| -> HashMap<usize, ()> {
+            map.entries.iter().map(|&addr| (addr, ())).collect()
+        };
+
+        let mut merged = ProjectMap {
+            labels: merge_field("label",
+              &base.labels, &ours.labels, &theirs.labels, &mut conflicts),
+            string_hints: merge_field("string hint", &base.string_hints,
+              &ours.string_hints, &theirs.string_hints, &mut conflicts),
+            comments: merge_field("comment",
+              &base.comments, &ours.comments, &theirs.comments, &mut conflicts),
+            regions: merge_field("region",
+              &regions(base), &regions(ours), &regions(theirs), &mut conflicts)
+              .into_values().flatten().collect(),
+            signatures: merge_field("signature", &base.signatures,
+              &ours.signatures, &theirs.signatures, &mut conflicts),
+            entries: merge_field("entry",
+              &entries(base), &entries(ours), &entries(theirs), &mut conflicts)
+              .into_keys().collect(),
+        };
+        merged.regions.sort_by_key(|r| r.start);
+        (merged, conflicts)
+    }
+}
+
+fn merge_field<V: Clone + PartialEq + Debug>(field: &'static str,
+  base: &HashMap<usize, V>, ours: &HashMap<usize, V>,
+  theirs: &HashMap<usize, V>, conflicts: &mut Vec<Conflict>
+  ) -> HashMap<usize, V> {
+    let addrs: BTreeSet<usize> = base.keys()
+      .chain(ours.keys())
+      .chain(theirs.keys())
+      .copied()
+      .collect();
+    let describe = |v: Option<&V>|
+      v.map_or_else(|| "nothing".to_string(), |v| format!("{:?}", v));
+
+    let mut merged = HashMap::new();
+    for addr in addrs {
+        let (b, o, t) = (base.get(&addr), ours.get(&addr), theirs.get(&addr));
+        let pick = if o == t || t == b {
+            o
+        } else if o == b {
+            t
+        } else {
+            conflicts.push(Conflict {
+                addr,
+                field,
+                ours: describe(o),
+                theirs: describe(t),
+            });
+            o
+        };
+        if let Some(v) = pick {
+            merged.insert(addr, v.clone());
+        }
+    }
+    merged
+}
@@ -0,0 +1,110 @@
+use std::{
+    fmt,
+    io::Cursor,
+    ops::Range,
+};
+
+use super::vm::{Error, Result, Vm, VmState, Instruction};
+
+// how far a run goes when no step limit is given, so one waiting on an
+//   address that's never reached still ends
+pub const DEFAULT_STEP_LIMIT: usize = 10_000_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopEvent {
+    // an `in` with nothing left in the supplied input
+    Input,
+    Address(usize),
+    Steps(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Event(StopEvent),
+    // an `in` with nothing left, when that wasn't asked to stop the run
+    InputExhausted,
+    Halted,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Event(StopEvent::Input) => write!(f, "waiting on input"),
+            StopReason::Event(StopEvent::Address(ip)) =>
+              write!(f, "reached address {}", ip),
+            StopReason::Event(StopEvent::Steps(n)) =>
+              write!(f, "reached step limit ({})", n),
+            StopReason::InputExhausted => write!(f, "ran out of input"),
+            StopReason::Halted => write!(f, "halted"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Unpacked {
+    pub vm: Vm,
+    pub steps: usize,
+    pub reason: StopReason,
+    pub output: Vec<u8>,
+}
+
+pub fn run_until(mut vm: Vm, events: &[StopEvent], input: &[u8]
+  ) -> Result<Unpacked> {
+    let mut input = Cursor::new(input);
+    let mut output = Vec::new();
+    let mut steps = 0;
+
+    let mut events = events.to_vec();
+    if !events.iter().any(|e| matches!(e, StopEvent::Steps(_))) {
+        events.push(StopEvent::Steps(DEFAULT_STEP_LIMIT));
+    }
+
+    let reason = loop {
+        let hit = events.iter().find(|e| match e {
+            StopEvent::Input => {
+                matches!(vm.decode_next(), Ok((_, Instruction::In(_))))
+                  && input.position() == input.get_ref().len() as u64
+            },
+            StopEvent::Address(ip) => vm.ip() == *ip,
+            StopEvent::Steps(n) => steps >= *n,
+        });
+        if let Some(e) = hit {
+            break StopReason::Event(*e);
+        }
+
+        match vm.step(&mut input, &mut output) {
+            Ok(VmState::Running) => { },
+            Ok(VmState::Halted) => break StopReason::Halted,
+            // the `in` didn't happen, so the run stops before it
+            Err(Error::IOError) => break StopReason::InputExhausted,
+            Err(e) => return Err(e),
+        };
+        steps += 1;
+    };
+
+    Ok(Unpacked { vm, steps, reason, output })
+}
+
+// contiguous runs of changed words, as half-open address ranges
+pub fn diff_regions(original: &[u16], unpacked: &[u16]) -> Vec<Range<usize>> {
+    let len = original.len().max(unpacked.len());
+    let word = |image: &[u16], i: usize| image.get(i).copied().unwrap_or(0);
+
+    let mut regions = Vec::new();
+    let mut start = None;
+    for i in 0..len {
+        let changed = word(original, i) != word(unpacked, i);
+        match (changed, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                regions.push(s..i);
+                start = None;
+            },
+            _ => { },
+        };
+    }
+    if let Some(s) = start {
+        regions.push(s..len);
+    }
+    regions
+}
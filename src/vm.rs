@@ -4,6 +4,8 @@ use std::{
     io::{Read, Write},
 };
 
+use super::isa::{self, Operand};
+
 #[derive(Debug, Copy, Clone)]
 pub enum Error {
     BadBinary,
@@ -16,6 +18,8 @@ pub enum Error {
     InvalidIOWord(u16),
     IOError,
     InvalidAddress(u16),
+    InvalidImmediate(u16),
+    InvalidRegister(usize),
 }
 
 pub const INDIRECT_BIT: u16 = 0b1000000000000000;
@@ -43,6 +47,10 @@ impl fmt::Display for Error {
             Error::IOError => write!(f, "I/O error"),
             Error::InvalidAddress(w) =>
               write!(f, "invalid memory address ({})", w),
+            Error::InvalidImmediate(w) =>
+              write!(f, "immediate out of range ({})", w),
+            Error::InvalidRegister(r) =>
+              write!(f, "invalid register ({})", r),
         }
     }
 }
@@ -57,12 +65,57 @@ pub enum VmState {
     Halted,
 }
 
+// a call we're inside of, as seen by the shadow call stack
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub call_site: usize,
+    pub target: usize,
+    pub return_addr: usize,
+    // the stack depth before the return address was pushed
+    pub stack_depth: usize,
+}
+
+// a `ret` that doesn't line up with the shadow call stack
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CallMismatch {
+    // nothing was called
+    Unmatched { ret_site: usize, to: usize },
+    // the return address isn't the innermost call's
+    WrongAddress { ret_site: usize, to: usize, expected: usize },
+    // the right address, but it was moved around on the stack
+    StackDepth { ret_site: usize, depth: usize, expected: usize },
+}
+
+impl fmt::Display for CallMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallMismatch::Unmatched { ret_site, to } =>
+              write!(f, "ret at {} to {} without a call", ret_site, to),
+            CallMismatch::WrongAddress { ret_site, to, expected } =>
+              write!(f, "ret at {} to {}, expected {}",
+                ret_site, to, expected),
+            CallMismatch::StackDepth { ret_site, depth, expected } =>
+              write!(f, "ret at {} from stack depth {}, expected {}",
+                ret_site, depth, expected),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Vm {
     memory: [u16; 32768],
     registers: [u16; 8],
     ip: usize,
     stack: Vec<u16>,
+    frames: Vec<Frame>,
+    // from the last instruction stepped
+    mismatch: Option<CallMismatch>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Vm {
@@ -72,6 +125,8 @@ impl Vm {
             registers: [0; 8],
             ip: 0,
             stack: Vec::new(),
+            frames: Vec::new(),
+            mismatch: None,
         }
     }
 
@@ -82,6 +137,8 @@ impl Vm {
 
         self.memory = [0; 32768];
         self.memory[0..program.len()].copy_from_slice(program);
+        self.frames.clear();
+        self.mismatch = None;
         Ok(())
     }
 
@@ -89,16 +146,16 @@ impl Vm {
     pub fn run<R: Read, W: Write>(&mut self, read: &mut R, write: &mut W
       ) -> Result<()> {
         loop {
-            match self.step(read, write)? {
-                VmState::Halted => return Ok(()),
-                _ => { }
-            };
+            if let VmState::Halted = self.step(read, write)? {
+                return Ok(());
+            }
         }
     }
 
     pub fn step<R: Read, W: Write>(&mut self, read: &mut R, write: &mut W
       ) -> Result<VmState> {
         let (mut new_ip, instr) = self.decode_next()?;
+        self.mismatch = None;
         match instr {
             Instruction::Halt => return Ok(VmState::Halted),
 
@@ -161,15 +218,28 @@ impl Vm {
                 self.read_src(&dst_addr), self.read_src(&src_addr))?,
 
             Instruction::Call(ip) => {
+                let target = self.read_src(&ip) as usize;
+                // a frame whose return address has been popped can't be
+                //   returned to, as with a call used to find its own address
+                let depth = self.stack.len();
+                self.frames.retain(|f| f.stack_depth < depth);
+                self.frames.push(Frame {
+                    call_site: self.ip,
+                    target,
+                    return_addr: new_ip,
+                    stack_depth: depth,
+                });
                 self.stack.push(new_ip as u16);
-                new_ip = self.read_src(&ip) as usize;
+                new_ip = target;
             },
 
             Instruction::Ret => {
+                let depth = self.stack.len();
                 match self.stack.pop() {
                     Some(ip) => new_ip = ip as usize,
                     None => return Ok(VmState::Halted),
                 };
+                self.mismatch = self.pop_frame(new_ip, depth - 1);
             },
 
             Instruction::Out(src) => {
@@ -219,6 +289,17 @@ impl Vm {
         &self.stack
     }
 
+    // innermost last
+    #[inline]
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    #[inline]
+    pub fn call_mismatch(&self) -> Option<CallMismatch> {
+        self.mismatch
+    }
+
     #[inline]
     pub fn decode(&self, addr: usize) -> Result<(usize, Instruction)> {
         Instruction::decode(&self.memory[..], addr)
@@ -254,6 +335,50 @@ impl Vm {
         self.stack.pop()
     }
 
+    #[inline]
+    pub fn stack_mut(&mut self) -> &mut Vec<u16> {
+        &mut self.stack
+    }
+
+    #[inline]
+    pub fn frames_mut(&mut self) -> &mut Vec<Frame> {
+        &mut self.frames
+    }
+
+    // keep the shadow call stack in step with a `ret` to `to`, which
+    //   popped its address from `depth`
+    fn pop_frame(&mut self, to: usize, depth: usize) -> Option<CallMismatch> {
+        let ret_site = self.ip;
+        let frame = match self.frames.last() {
+            Some(frame) => *frame,
+            None => return Some(CallMismatch::Unmatched { ret_site, to }),
+        };
+
+        if frame.return_addr != to {
+            // unwinding several frames at once keeps the outer ones; a
+            //   return somewhere else entirely gives up on the innermost
+            match self.frames.iter().rposition(|f| f.return_addr == to) {
+                Some(i) => self.frames.truncate(i),
+                None => { self.frames.pop(); },
+            };
+            return Some(CallMismatch::WrongAddress {
+                ret_site,
+                to,
+                expected: frame.return_addr,
+            });
+        }
+
+        self.frames.pop();
+        if frame.stack_depth != depth {
+            return Some(CallMismatch::StackDepth {
+                ret_site,
+                depth,
+                expected: frame.stack_depth,
+            });
+        }
+        None
+    }
+
     #[inline]
     fn read_src(&self, operand: &SrcOperand) -> u16 {
         match *operand {
@@ -282,7 +407,7 @@ impl Vm {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SrcOperand {
     Immediate(u16),
     Register(usize),
@@ -305,9 +430,18 @@ impl SrcOperand {
     pub fn decode_at(memory: &[u16], ip: usize) -> Result<Self> {
         Self::decode(*memory.get(ip).ok_or(Error::InvalidIp(ip))?)
     }
+
+    pub fn encode(&self) -> Result<u16> {
+        match *self {
+            SrcOperand::Immediate(val) if val & INDIRECT_BIT != 0 =>
+              Err(Error::InvalidImmediate(val)),
+            SrcOperand::Immediate(val) => Ok(val),
+            SrcOperand::Register(reg) => encode_register(reg),
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DstOperand {
     Register(usize)
 }
@@ -329,9 +463,31 @@ impl DstOperand {
     pub fn decode_at(memory: &[u16], ip: usize) -> Result<Self> {
         Self::decode(*memory.get(ip).ok_or(Error::InvalidIp(ip))?)
     }
+
+    pub fn encode(&self) -> Result<u16> {
+        match *self {
+            DstOperand::Register(reg) => encode_register(reg),
+        }
+    }
+
+    #[inline]
+    pub fn register(&self) -> usize {
+        match *self {
+            DstOperand::Register(reg) => reg,
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[inline]
+fn encode_register(reg: usize) -> Result<u16> {
+    if reg & !(REGISTER_MASK as usize) != 0 {
+        Err(Error::InvalidRegister(reg))
+    } else {
+        Ok(INDIRECT_BIT | reg as u16)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Instruction {
     Halt,
     Set(DstOperand, SrcOperand),
@@ -358,89 +514,32 @@ pub enum Instruction {
 }
 
 impl Instruction {
+    pub fn encode(&self) -> Result<Vec<u16>> {
+        let mut words = vec![self.opcode()];
+        for operand in self.operands() {
+            words.push(operand.encode()?);
+        }
+        Ok(words)
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.info().size()
+    }
+
     pub fn decode(memory: &[u16], ip: usize) -> Result<(usize, Instruction)> {
-        match *memory.get(ip).ok_or(Error::InvalidIp(ip))? {
-            0 => Ok((ip + 1, Instruction::Halt)),
-            1 => Ok((ip + 3, Instruction::Set(
-                   DstOperand::decode_at(memory, ip + 1)?,
-                   SrcOperand::decode_at(memory, ip + 2)?
-                 ))),
-            2 => Ok((ip + 2, Instruction::Push(
-                   SrcOperand::decode_at(memory, ip + 1)?
-                 ))),
-            3 => Ok((ip + 2, Instruction::Pop(
-                   DstOperand::decode_at(memory, ip + 1)?
-                 ))),
-            4 => Ok((ip + 4, Instruction::Eq(
-                   DstOperand::decode_at(memory, ip + 1)?,
-                   SrcOperand::decode_at(memory, ip + 2)?,
-                   SrcOperand::decode_at(memory, ip + 3)?
-                 ))),
-            5 => Ok((ip + 4, Instruction::Gt(
-                   DstOperand::decode_at(memory, ip + 1)?,
-                   SrcOperand::decode_at(memory, ip + 2)?,
-                   SrcOperand::decode_at(memory, ip + 3)?
-                 ))),
-            6 => Ok((ip + 2, Instruction::Jmp(
-                   SrcOperand::decode_at(memory, ip + 1)?
-                 ))),
-            7 => Ok((ip + 3, Instruction::Jt(
-                   SrcOperand::decode_at(memory, ip + 1)?,
-                   SrcOperand::decode_at(memory, ip + 2)?
-                 ))),
-            8 => Ok((ip + 3, Instruction::Jf(
-                   SrcOperand::decode_at(memory, ip + 1)?,
-                   SrcOperand::decode_at(memory, ip + 2)?
-                 ))),
-            9 => Ok((ip + 4, Instruction::Add(
-                   DstOperand::decode_at(memory, ip + 1)?,
-                   SrcOperand::decode_at(memory, ip + 2)?,
-                   SrcOperand::decode_at(memory, ip + 3)?
-                 ))),
-            10 => Ok((ip + 4, Instruction::Mult(
-                    DstOperand::decode_at(memory, ip + 1)?,
-                    SrcOperand::decode_at(memory, ip + 2)?,
-                    SrcOperand::decode_at(memory, ip + 3)?
-                  ))),
-            11 => Ok((ip + 4, Instruction::Mod(
-                    DstOperand::decode_at(memory, ip + 1)?,
-                    SrcOperand::decode_at(memory, ip + 2)?,
-                    SrcOperand::decode_at(memory, ip + 3)?
-                  ))),
-            12 => Ok((ip + 4, Instruction::And(
-                    DstOperand::decode_at(memory, ip + 1)?,
-                    SrcOperand::decode_at(memory, ip + 2)?,
-                    SrcOperand::decode_at(memory, ip + 3)?
-                  ))),
-            13 => Ok((ip + 4, Instruction::Or(
-                    DstOperand::decode_at(memory, ip + 1)?,
-                    SrcOperand::decode_at(memory, ip + 2)?,
-                    SrcOperand::decode_at(memory, ip + 3)?
-                  ))),
-            14 => Ok((ip + 3, Instruction::Not(
-                    DstOperand::decode_at(memory, ip + 1)?,
-                    SrcOperand::decode_at(memory, ip + 2)?
-                  ))),
-            15 => Ok((ip + 3, Instruction::Rmem(
-                    DstOperand::decode_at(memory, ip + 1)?,
-                    SrcOperand::decode_at(memory, ip + 2)?
-                  ))),
-            16 => Ok((ip + 3, Instruction::Wmem(
-                    SrcOperand::decode_at(memory, ip + 1)?,
-                    SrcOperand::decode_at(memory, ip + 2)?
-                  ))),
-            17 => Ok((ip + 2, Instruction::Call(
-                    SrcOperand::decode_at(memory, ip + 1)?
-                  ))),
-            18 => Ok((ip + 1, Instruction::Ret)),
-            19 => Ok((ip + 2, Instruction::Out(
-                    SrcOperand::decode_at(memory, ip + 1)?
-                  ))),
-            20 => Ok((ip + 2, Instruction::In(
-                    DstOperand::decode_at(memory, ip + 1)?
-                  ))),
-            21 => Ok((ip + 1, Instruction::Noop)),
-            word => Err(Error::IllegalInstruction(word)),
+        let opcode = *memory.get(ip).ok_or(Error::InvalidIp(ip))?;
+        let info = isa::by_opcode(opcode)
+          .ok_or(Error::IllegalInstruction(opcode))?;
+
+        let mut operands = [Operand::Src(SrcOperand::Immediate(0)); 3];
+        for (i, kind) in info.operands.iter().enumerate() {
+            let ptr = ip + 1 + i;
+            let word = *memory.get(ptr).ok_or(Error::InvalidIp(ptr))?;
+            operands[i] = Operand::decode(*kind, word)?;
         }
+
+        let n = info.operands.len();
+        Ok((ip + 1 + n, Instruction::from_operands(opcode, &operands[..n])?))
     }
 }
@@ -0,0 +1,192 @@
+use std::{env, fs};
+
+use synacor_vm::{
+    asm::AsmError,
+    assembler::{assemble, assemble_file},
+    binary,
+};
+
+fn error(source: &str) -> String {
+    assemble(source).unwrap_err().to_string()
+}
+
+#[test]
+fn operator_precedence() {
+    assert_eq!(assemble("
+        .word 1 + 2 * 3, (1 + 2) * 3
+        .word 10 - 3 - 2, 7 / 2 % 2
+        .word 1 << 2 + 1, 1 | 2 ^ 3 & 6
+        .word -1, ~0, -(2 - 5)
+    ").unwrap(), vec![7, 9, 5, 1, 8, 1, 32767, 32767, 3]);
+}
+
+#[test]
+fn here() {
+    assert_eq!(assemble("
+        noop
+        .word $, $ + 1
+        jmp $
+    ").unwrap(), vec![21, 1, 2, 6, 3]);
+
+    // a constant's `$` is where it was defined, not where it's used
+    assert_eq!(assemble("
+        noop
+        noop
+        noop
+        .equ x, $
+        .word x
+        .word x
+        y = $ - 1
+        .word y
+    ").unwrap(), vec![21, 21, 21, 3, 3, 4]);
+}
+
+#[test]
+fn constants() {
+    assert_eq!(assemble("
+        size = 4
+        .equ twice, size * 2
+        .const more, later + twice
+        .word twice, more
+        later = 100
+    ").unwrap(), vec![8, 108]);
+}
+
+#[test]
+fn redefinitions() {
+    assert!(error("a: noop\na: noop\n").contains("duplicate symbol \"a\""));
+    assert!(error("x = 1\nx = 2\n").contains("duplicate symbol \"x\""));
+    assert!(error(".equ x, 1\nx: noop\n").contains("duplicate symbol \"x\""));
+    assert!(error("x = y\ny = x + 1\n.word x\n")
+      .contains("is defined in terms of itself"));
+    assert!(matches!(assemble(".word 1 / (2 - 2)\n"),
+      Err(AsmError::SyntaxError(pos, msg))
+        if pos.line == 1 && msg == "division by zero"));
+}
+
+#[test]
+fn forward_references() {
+    let image = assemble("
+        start: jmp end
+        call sub
+        sub: ret
+        end: set r0, start
+        halt
+    ").unwrap();
+    assert_eq!(image, vec![6, 5, 17, 4, 18, 1, 32768, 0, 0]);
+
+    // and it reads back as a binary image
+    let bytes: Vec<u8> = image.iter().flat_map(|w| w.to_le_bytes()).collect();
+    assert_eq!(binary::read_binary(&bytes).unwrap(), image);
+}
+
+#[test]
+fn errors_have_positions() {
+    assert_eq!(error("noop\n  jmp nowhere\n"),
+      "syntax error at 2:7: undefined symbol \"nowhere\"");
+    assert_eq!(error("bogus r0\n"),
+      "syntax error at 1:1: unknown mnemonic \"bogus\"");
+    assert_eq!(error("halt\nset 5, r0\n"),
+      "syntax error at 2:5: destination operand must be a register");
+    assert_eq!(error("add r0, r1\n"),
+      "syntax error at 1:1: expected 3 operands");
+    assert_eq!(error("out 'x\n"),
+      "syntax error at 1:5: unterminated char literal");
+    assert_eq!(error("out 32768\n"),
+      "syntax error at 1:5: immediate out of range (32768)");
+}
+
+#[test]
+fn directives() {
+    assert_eq!(assemble("
+        .org 2
+        .word 1, 'a', 0xffff
+        .str \"hi\"
+        .ascii \"ok\\n\"
+        .out \"!\"
+    ").unwrap(),
+      vec![0, 0, 1, 97, 65535, 2, 104, 105, 111, 107, 10, 19, 33]);
+
+    assert!(error(".bogus 1\n").contains("unknown directive \".bogus\""));
+    assert!(error(".str 5\n").contains("expected a single string literal"));
+    assert!(error(".org 1\nnoop\n.org 1\nhalt\n")
+      .contains("overlapping output at address 1"));
+    assert!(error(".org 32767\nnoop\nnoop\n")
+      .contains("statement extends past end of memory"));
+}
+
+#[test]
+fn arithmetic_is_mod_32768() {
+    assert_eq!(assemble("
+        .word 32767 + 1, 0 - 1, 200 * 200, 1 << 15
+        out 32767 + 'A' + 1
+    ").unwrap(), vec![0, 32767, 7232, 0, 19, 65]);
+}
+
+#[test]
+fn local_labels() {
+    assert_eq!(assemble("
+        one: jmp .next
+        .next: ret
+        two: jmp .next
+        .next: ret
+        jmp one.next
+    ").unwrap(), vec![6, 2, 18, 6, 5, 18, 6, 2]);
+
+    assert!(error(".early: noop\n").contains("before any global label"));
+}
+
+#[test]
+fn macros() {
+    assert_eq!(assemble("
+        .macro twice reg, n
+        add reg, reg, n
+        .loop: jt reg, .loop
+        add reg, reg, n
+        .endm
+        start: twice r0, 2
+        twice r1, 3
+    ").unwrap(), vec![
+        9, 32768, 32768, 2, 7, 32768, 4, 9, 32768, 32768, 2,
+        9, 32769, 32769, 3, 7, 32769, 15, 9, 32769, 32769, 3,
+    ]);
+
+    let err = error("
+        .macro store dst
+        wmem dst, r9
+        .endm
+        store 100
+    ");
+    assert!(err.contains("in expansion of macro \"store\" at 5:9"), "{}", err);
+
+    assert!(error(".macro m a\nnoop\n.endm\nm\n")
+      .contains("macro \"m\" takes 1 arguments"));
+    assert!(error(".macro add\n.endm\n").contains("shadows a mnemonic"));
+    assert!(error(".macro m\nnoop\n").contains("unterminated macro"));
+    assert!(error(".endm\n").contains("outside of a macro"));
+}
+
+#[test]
+fn includes() {
+    let dir = env::temp_dir().join(format!("synasm-{}", std::process::id()));
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("main.asm"),
+      ".include \"lib/defs.asm\"\nstart: out greeting\nhalt\n").unwrap();
+    // relative to the including file
+    fs::write(dir.join("lib/defs.asm"),
+      ".include \"more.asm\"\ngreeting = 'h'\n").unwrap();
+    fs::write(dir.join("lib/more.asm"), ".word 7\n").unwrap();
+    fs::write(dir.join("loop.asm"), ".include \"loop.asm\"\n").unwrap();
+    fs::write(dir.join("missing.asm"), "noop\n.include \"nope.asm\"\n")
+      .unwrap();
+
+    let image = assemble_file(dir.join("main.asm"));
+    let cycle = assemble_file(dir.join("loop.asm")).unwrap_err().to_string();
+    let missing = assemble_file(dir.join("missing.asm"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(image.unwrap(), vec![7, 19, 104, 0]);
+    assert!(cycle.contains("loop.asm:1:1: includes nested too deeply"),
+      "{}", cycle);
+    assert!(matches!(missing, Err(AsmError::IOError(_))));
+}
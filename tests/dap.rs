@@ -0,0 +1,252 @@
+use std::{
+    env,
+    fs,
+    io::{BufRead, Cursor, Read},
+};
+
+use serde_json::{json, Value};
+
+use synacor_vm::{
+    assembler::assemble,
+    dap::{DapError, DapServer},
+    vm::Vm,
+};
+
+const PROGRAM: &str = "
+    call greet
+    in r0
+    in r1
+    out r0
+    out r1
+    halt
+    greet: out 'h'
+    out 'i'
+    out 10
+    ret
+";
+
+fn frame(requests: &[Value]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for (seq, req) in requests.iter().enumerate() {
+        let mut req = req.clone();
+        // recorded requests come numbered
+        if req["seq"].is_null() {
+            req["seq"] = json!(seq + 1);
+            req["type"] = json!("request");
+        }
+        let body = req.to_string();
+        stream.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+          .bytes());
+    }
+    stream
+}
+
+fn unframe(mut stream: &[u8]) -> Vec<Value> {
+    let mut msgs = Vec::new();
+    while !stream.is_empty() {
+        let mut header = String::new();
+        stream.read_line(&mut header).unwrap();
+        let len = header.trim_end()
+          .strip_prefix("Content-Length: ").unwrap()
+          .parse().unwrap();
+        let mut blank = String::new();
+        stream.read_line(&mut blank).unwrap();
+        assert_eq!(blank, "\r\n");
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).unwrap();
+        msgs.push(serde_json::from_slice(&body).unwrap());
+    }
+    msgs
+}
+
+fn session(vm: Option<Vm>, requests: &[Value]) -> Vec<Value> {
+    let mut output = Vec::new();
+    let mut server = DapServer::new(vm);
+    server.serve(Cursor::new(frame(requests)), &mut output).unwrap();
+    unframe(&output)
+}
+
+// a recording is one message per line, as they went back and forth;
+//   we play the requests and expect the rest
+fn replay(vm: Option<Vm>, recording: &str) {
+    let (requests, expected): (Vec<Value>, Vec<Value>) = recording.lines()
+      .map(|line| serde_json::from_str::<Value>(line).unwrap())
+      .partition(|msg| msg["type"] == "request");
+    let msgs = session(vm, &requests);
+    for (i, (msg, expected)) in msgs.iter().zip(&expected).enumerate() {
+        assert_eq!(msg, expected, "message {}", i);
+    }
+    assert_eq!(msgs.len(), expected.len());
+}
+
+fn response<'a>(msgs: &'a [Value], command: &str) -> &'a Value {
+    msgs.iter()
+      .find(|msg| msg["type"] == "response" && msg["command"] == command)
+      .unwrap()
+}
+
+#[test]
+fn recorded_attach() {
+    let mut vm = Vm::new();
+    vm.load(&assemble(PROGRAM).unwrap()).unwrap();
+    // labels don't survive assembly, so `greet` is only known as `fn0`
+    replay(Some(vm), include_str!("recordings/dap_attach.jsonl"));
+}
+
+#[test]
+fn launch() {
+    let dir = env::temp_dir().join(format!("syndap-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let image: Vec<u8> = assemble(PROGRAM).unwrap().iter()
+      .flat_map(|word| word.to_le_bytes())
+      .collect();
+    fs::write(dir.join("greet.bin"), image).unwrap();
+    fs::write(dir.join("greet.map"), "11\tgreet\n").unwrap();
+    fs::write(dir.join("input"), "ab").unwrap();
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    let msgs = session(None, &[
+        json!({ "command": "initialize", "arguments": { } }),
+        json!({ "command": "launch", "arguments": {
+            "program": path("greet.bin"),
+            "map": path("greet.map"),
+            "input": path("input"),
+        } }),
+        json!({ "command": "setFunctionBreakpoints",
+          "arguments": { "breakpoints": [{ "name": "greet" }] } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "variables",
+          "arguments": { "variablesReference": 1000 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "disconnect" }),
+    ]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    let stops: Vec<_> = msgs.iter()
+      .filter(|msg| msg["event"] == "stopped")
+      .map(|msg| &msg["body"]["reason"])
+      .collect();
+    assert_eq!(stops, ["breakpoint"]);
+
+    let words = &response(&msgs, "variables")["body"]["variables"];
+    assert_eq!(words.as_array().unwrap().len(), 256);
+    assert_eq!(words[11], json!({
+        "name": "0x000b greet",
+        "value": "19 (0x0013)",
+        "variablesReference": 0,
+    }));
+    assert_eq!(
+      response(&msgs, "stackTrace")["body"]["stackFrames"][0]["name"],
+      "greet (11)");
+
+    let output: String = msgs.iter()
+      .filter(|msg| msg["event"] == "output")
+      .map(|msg| msg["body"]["output"].as_str().unwrap())
+      .collect();
+    assert_eq!(output, "hi\nab");
+    assert!(msgs.iter().any(|msg| msg["event"] == "terminated"));
+}
+
+#[test]
+fn failures() {
+    let msgs = session(None, &[
+        json!({ "command": "attach", "arguments": { } }),
+        json!({ "command": "launch",
+          "arguments": { "program": "/nonexistent/game.bin" } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "readMemory", "arguments": { } }),
+        json!({ "command": "disconnect" }),
+    ]);
+    let failures: Vec<_> = msgs.iter()
+      .filter(|msg| msg["success"] == false)
+      .map(|msg| msg["command"].as_str().unwrap())
+      .collect();
+    assert_eq!(failures, ["attach", "launch", "stackTrace", "readMemory"]);
+    assert!(!msgs.iter().any(|msg| msg["event"] == "initialized"));
+}
+
+fn attached(source: &str) -> Vm {
+    let mut vm = Vm::new();
+    vm.load(&assemble(source).unwrap()).unwrap();
+    vm
+}
+
+#[test]
+fn leaving_while_running() {
+    let start = [
+        json!({ "command": "initialize", "arguments": { } }),
+        json!({ "command": "attach", "arguments": { "stopOnEntry": false } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "threads" }),
+    ];
+
+    // the threads request waits for the target to stop, which it
+    //   never does; disconnecting goes ahead of it
+    let mut requests = start.to_vec();
+    requests.push(json!({ "command": "disconnect" }));
+    let msgs = session(Some(attached("loop: jmp loop\n")), &requests);
+    assert_eq!(response(&msgs, "disconnect")["success"], true);
+    assert!(!msgs.iter().any(|msg| msg["command"] == "threads"));
+    assert!(!msgs.iter().any(|msg| msg["event"] == "stopped"));
+
+    // and a client that just hangs up stops it too
+    let mut server = DapServer::new(Some(attached("loop: jmp loop\n")));
+    let res = server.serve(Cursor::new(frame(&start)), &mut Vec::new());
+    assert!(matches!(res, Err(DapError::Disconnected)));
+}
+
+#[test]
+fn disassembly_follows_writes() {
+    let disassemble = json!({ "command": "disassemble", "arguments": {
+        "memoryReference": "0x0004",
+        "instructionCount": 1,
+    } });
+    let msgs = session(Some(attached("wmem 4, 21\nnoop\nhalt\n")), &[
+        json!({ "command": "initialize", "arguments": { } }),
+        json!({ "command": "attach", "arguments": { } }),
+        json!({ "command": "configurationDone" }),
+        disassemble.clone(),
+        json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+        disassemble.clone(),
+        json!({ "command": "setVariable", "arguments": {
+            "variablesReference": 1000,
+            "name": "0x0004",
+            "value": "0",
+        } }),
+        disassemble,
+        json!({ "command": "disconnect" }),
+    ]);
+
+    let listings: Vec<_> = msgs.iter()
+      .filter(|msg| msg["command"] == "disassemble")
+      .map(|msg| &msg["body"]["instructions"][0]["instruction"])
+      .collect();
+    assert_eq!(listings, ["halt", "noop", "halt"]);
+}
+
+#[test]
+fn ip_stays_in_memory() {
+    let set_ip = |value| json!({ "command": "setVariable", "arguments": {
+        "variablesReference": 1,
+        "name": "ip",
+        "value": value,
+    } });
+    let msgs = session(Some(attached("noop\nhalt\n")), &[
+        json!({ "command": "initialize", "arguments": { } }),
+        json!({ "command": "attach", "arguments": { } }),
+        json!({ "command": "configurationDone" }),
+        set_ip("0x8000"),
+        set_ip("1"),
+        json!({ "command": "disconnect" }),
+    ]);
+
+    let replies: Vec<_> = msgs.iter()
+      .filter(|msg| msg["command"] == "setVariable")
+      .map(|msg| (&msg["success"], &msg["message"]))
+      .collect();
+    assert_eq!(replies, [
+        (&json!(false), &json!("no address 32768")),
+        (&json!(true), &Value::Null),
+    ]);
+}
@@ -0,0 +1,147 @@
+use synacor_vm::{
+    assembler::assemble,
+    asm::{DisAsmOpts, ImageMap, Xref, XrefKind},
+    map::ProjectMap,
+};
+
+fn disasm(image: &[u16], opts: &DisAsmOpts) -> String {
+    let mut text = Vec::new();
+    ImageMap::new(image, opts).disasm(&mut text, opts).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+const PROGRAM: &str = "
+    call print
+    rmem r0, count
+    set r1, table
+    rmem r2, r1
+    wmem count, r2
+    jt r0, 0
+    halt
+    print: out 'x'
+    ret
+    count: .word 1000
+    table: .word 2000, 3000
+";
+
+#[test]
+fn round_trip_reassembles_exactly() {
+    let mut image = assemble(PROGRAM).unwrap();
+    image.extend([
+        // a register that doesn't exist
+        1, 32776, 5,
+        // quotes, and words no literal fits
+        19, '\'' as u16, 65535, 32768,
+        // a jump into the middle of an instruction
+        6, 1,
+    ]);
+
+    let opts = DisAsmOpts { round_trip: true, ..DisAsmOpts::default() };
+    let text = disasm(&image, &opts);
+    assert_eq!(assemble(&text).unwrap(), image, "{}", text);
+
+    // the plain listing is for reading, and doesn't promise this
+    assert!(assemble(&disasm(&image, &DisAsmOpts::default())).is_err());
+}
+
+#[test]
+fn xrefs_are_listed_above_labels() {
+    let image = assemble(PROGRAM).unwrap();
+    let opts = DisAsmOpts { xrefs: true, ..DisAsmOpts::default() };
+    let text = disasm(&image, &opts);
+    let lines: Vec<_> = text.lines().collect();
+
+    assert_eq!(lines[..2],
+      ["; xref: jump from lbl3+14 (14)", "lbl3: call fn0"]);
+    let fn0 = lines.iter().position(|l| l.starts_with("fn0:")).unwrap();
+    assert_eq!(lines[fn0 - 1], "; xref: call from lbl3 (0)");
+    let data1 = lines.iter().position(|l| l.starts_with("data1:")).unwrap();
+    assert_eq!(lines[data1 - 1],
+      "; xref: read from lbl3+2 (2), write from lbl3+11 (11)");
+
+    let map = ImageMap::new(&image, &opts);
+    assert_eq!(map.xrefs[&21], [
+        Xref { from: 2, kind: XrefKind::Read },
+        Xref { from: 11, kind: XrefKind::Write },
+    ]);
+    // only where they're asked for
+    assert!(!disasm(&image, &DisAsmOpts::default()).contains("xref"));
+}
+
+#[test]
+fn memory_operands_get_data_labels() {
+    let image = assemble(PROGRAM).unwrap();
+    let text = disasm(&image, &DisAsmOpts::default());
+    for line in [
+        "rmem r0, data1",
+        // a constant that's later used as an address
+        "set r1, data2",
+        "wmem data1, r2",
+        "data1: 1000",
+        "data2: 2000",
+    ] {
+        assert!(text.lines().any(|l| l == line), "{}\n{}", line, text);
+    }
+
+    let opts = DisAsmOpts { autolabel: false, ..DisAsmOpts::default() };
+    let text = disasm(&image, &opts);
+    assert!(text.contains("set r1, 22\n"), "{}", text);
+    assert!(!text.contains("data"), "{}", text);
+
+    // only constants that reach memory operands count
+    let image = assemble("set r0, 8\nadd r1, r0, 1\nhalt\n.word 1\n")
+      .unwrap();
+    assert!(!disasm(&image, &DisAsmOpts::default()).contains("data"));
+}
+
+#[test]
+fn map_annotations_are_rendered() {
+    let project = ProjectMap::read(&mut concat!(
+        "18\tprint\n",
+        "18\t.sig\tprint()\n",
+        "18\t.comment\tprints an x\n",
+        "18\t.comment\tand returns\n",
+        "21\t.region\t2\tstruct\tpair\n",
+    ).as_bytes()).unwrap();
+    let opts = DisAsmOpts {
+        initial_labels: Some(project.labels.clone()),
+        comments: project.comments.clone(),
+        signatures: project.signatures.clone(),
+        regions: project.regions.clone(),
+        ..DisAsmOpts::default()
+    };
+    let text = disasm(&assemble(PROGRAM).unwrap(), &opts);
+    let lines: Vec<_> = text.lines().collect();
+
+    // the signature comes first, then comments in the order given
+    let print = lines.iter().position(|l| l.starts_with("print:")).unwrap();
+    assert_eq!(lines[print - 3..=print], [
+        "; print()",
+        "; prints an x",
+        "; and returns",
+        "print: out 'x'",
+    ]);
+    assert!(text.contains("call print\n"), "{}", text);
+
+    let pair = lines.iter().position(|l| l.contains("struct pair")).unwrap();
+    assert_eq!(lines[pair], "; struct pair (2 words)");
+    assert!(lines[pair + 1].ends_with("1000"), "{}", text);
+}
+
+#[test]
+fn entry_points_start_code() {
+    // jmp 3, then a push whose operand is really the jump's target
+    let image = [6, 3, 2, 19, 'A' as u16, 0];
+    let project = ProjectMap::read(&mut "3\t.entry\n3\t.region\t2\tdata\n"
+      .as_bytes()).unwrap();
+    let opts = DisAsmOpts {
+        entries: project.entries.clone(),
+        regions: project.regions.clone(),
+        ..DisAsmOpts::default()
+    };
+    assert_eq!(disasm(&image, &opts),
+      "jmp entry0\n2\n; entry\nentry0: out 'A'\nhalt\n");
+
+    let opts = DisAsmOpts { autolabel: false, ..opts };
+    assert_eq!(disasm(&image, &opts), "jmp 3\n2\n; entry\nout 'A'\nhalt\n");
+}
@@ -0,0 +1,45 @@
+use synacor_vm::{
+    effects::{IoEffect, StackEffect, Successor},
+    vm::{DstOperand, Instruction, SrcOperand, Vm},
+};
+
+#[test]
+fn static_effects() {
+    let fx = Instruction::Wmem(SrcOperand::Register(1), SrcOperand::Register(2))
+      .effects(100);
+    assert_eq!(fx.reads.iter().collect::<Vec<_>>(), vec![1, 2]);
+    assert!(fx.writes.is_empty());
+    assert_eq!(fx.mem_write, Some(SrcOperand::Register(1)));
+    assert_eq!(fx.mem_read, None);
+    assert_eq!(fx.successors, vec![Successor::Next(103)]);
+
+    let fx = Instruction::Call(SrcOperand::Immediate(500)).effects(10);
+    assert_eq!(fx.stack, StackEffect { pops: 0, pushes: 1 });
+    assert_eq!(fx.successors,
+      vec![Successor::Target(SrcOperand::Immediate(500)), Successor::Next(12)]);
+
+    let fx = Instruction::In(DstOperand::Register(3)).effects(0);
+    assert_eq!(fx.writes.iter().collect::<Vec<_>>(), vec![3]);
+    assert_eq!(fx.io, Some(IoEffect::Input));
+
+    assert!(Instruction::Halt.effects(0).successors.is_empty());
+}
+
+#[test]
+fn resolved_effects() {
+    let mut vm = Vm::new();
+    vm.registers_mut()[0] = 1234;
+    vm.registers_mut()[5] = 77;
+    vm.push_stack(42);
+
+    let fx = Instruction::Rmem(DstOperand::Register(1), SrcOperand::Register(0))
+      .effects(0).resolve(&vm);
+    assert_eq!(fx.mem_read, Some(1234));
+
+    let fx = Instruction::Jt(SrcOperand::Register(0), SrcOperand::Register(5))
+      .effects(20).resolve(&vm);
+    assert_eq!(fx.successors, vec![Some(23), Some(77)]);
+
+    let fx = Instruction::Ret.effects(0).resolve(&vm);
+    assert_eq!(fx.successors, vec![Some(42)]);
+}
@@ -0,0 +1,140 @@
+use synacor_vm::{
+    isa::{Operand, OperandKind, OPCODES},
+    vm::{
+        DstOperand,
+        Error,
+        Instruction,
+        SrcOperand,
+        INDIRECT_BIT,
+    },
+};
+
+use proptest::prelude::*;
+
+fn src_operand() -> impl Strategy<Value = SrcOperand> {
+    prop_oneof![
+        (0..INDIRECT_BIT).prop_map(SrcOperand::Immediate),
+        (0..8usize).prop_map(SrcOperand::Register),
+    ]
+}
+
+fn dst_operand() -> impl Strategy<Value = DstOperand> {
+    (0..8usize).prop_map(DstOperand::Register)
+}
+
+fn instruction() -> impl Strategy<Value = Instruction> {
+    let (s, d) = (src_operand, dst_operand);
+    prop_oneof![
+        Just(Instruction::Halt),
+        (d(), s()).prop_map(|(a, b)| Instruction::Set(a, b)),
+        s().prop_map(Instruction::Push),
+        d().prop_map(Instruction::Pop),
+        (d(), s(), s()).prop_map(|(a, b, c)| Instruction::Eq(a, b, c)),
+        (d(), s(), s()).prop_map(|(a, b, c)| Instruction::Gt(a, b, c)),
+        s().prop_map(Instruction::Jmp),
+        (s(), s()).prop_map(|(a, b)| Instruction::Jt(a, b)),
+        (s(), s()).prop_map(|(a, b)| Instruction::Jf(a, b)),
+        (d(), s(), s()).prop_map(|(a, b, c)| Instruction::Add(a, b, c)),
+        (d(), s(), s()).prop_map(|(a, b, c)| Instruction::Mult(a, b, c)),
+        (d(), s(), s()).prop_map(|(a, b, c)| Instruction::Mod(a, b, c)),
+        (d(), s(), s()).prop_map(|(a, b, c)| Instruction::And(a, b, c)),
+        (d(), s(), s()).prop_map(|(a, b, c)| Instruction::Or(a, b, c)),
+        (d(), s()).prop_map(|(a, b)| Instruction::Not(a, b)),
+        (d(), s()).prop_map(|(a, b)| Instruction::Rmem(a, b)),
+        (s(), s()).prop_map(|(a, b)| Instruction::Wmem(a, b)),
+        s().prop_map(Instruction::Call),
+        Just(Instruction::Ret),
+        s().prop_map(Instruction::Out),
+        d().prop_map(Instruction::In),
+        Just(Instruction::Noop),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn decode_inverts_encode(instr in instruction()) {
+        let words = instr.encode().unwrap();
+        prop_assert_eq!(words.len(), instr.size());
+        prop_assert_eq!(Instruction::decode(&words, 0).unwrap(),
+          (words.len(), instr));
+    }
+
+    #[test]
+    fn encode_inverts_decode(words in prop::collection::vec(any::<u16>(), 4)) {
+        if let Ok((len, instr)) = Instruction::decode(&words, 0) {
+            prop_assert_eq!(instr.encode().unwrap(), &words[..len]);
+        }
+    }
+}
+
+// every opcode with every register/immediate shape of its operands
+#[test]
+fn every_opcode_and_operand_kind_round_trips() {
+    let operand_words = [0, 1, 'a' as u16, INDIRECT_BIT - 1,
+      INDIRECT_BIT, INDIRECT_BIT + 7];
+
+    for opcode in 0..=21u16 {
+        let mut tried = 0;
+        for a in &operand_words {
+            for b in &operand_words {
+                for c in &operand_words {
+                    let words = [opcode, *a, *b, *c];
+                    if let Ok((len, instr)) = Instruction::decode(&words, 0) {
+                        assert_eq!(instr.opcode(), opcode);
+                        assert_eq!(instr.encode().unwrap(), &words[..len]);
+                        tried += 1;
+                    }
+                }
+            }
+        }
+        assert!(tried > 0, "opcode {} never decoded", opcode);
+    }
+}
+
+#[test]
+fn out_of_range_operands_are_errors() {
+    assert!(matches!(
+      Instruction::Push(SrcOperand::Immediate(INDIRECT_BIT)).encode(),
+      Err(Error::InvalidImmediate(w)) if w == INDIRECT_BIT));
+    assert!(matches!(
+      Instruction::Push(SrcOperand::Register(8)).encode(),
+      Err(Error::InvalidRegister(8))));
+    assert!(matches!(
+      Instruction::Pop(DstOperand::Register(9)).encode(),
+      Err(Error::InvalidRegister(9))));
+}
+
+// the variants are written out by hand, so check them against the table
+#[test]
+fn variants_match_the_opcode_table() {
+    let operand = |kind: &OperandKind, n| match kind {
+        OperandKind::Src => Operand::Src(SrcOperand::Immediate(n as u16)),
+        OperandKind::Dst => Operand::Dst(DstOperand::Register(n)),
+    };
+
+    for info in &OPCODES {
+        let operands: Vec<_> = info.operands.iter().enumerate()
+          .map(|(n, kind)| operand(kind, n))
+          .collect();
+        let instr = Instruction::from_operands(info.opcode, &operands)
+          .unwrap();
+        assert_eq!(instr.opcode(), info.opcode);
+        assert_eq!(instr.info().mnemonic, info.mnemonic);
+        assert_eq!(instr.operands(), operands, "{}", info.mnemonic);
+        assert_eq!(instr.size(), 1 + operands.len());
+
+        // the wrong shape of operands is never some other variant
+        let mut extra = operands.clone();
+        extra.push(operand(&OperandKind::Src, 0));
+        assert!(Instruction::from_operands(info.opcode, &extra).is_err());
+        for n in 0..operands.len() {
+            let mut swapped = operands.clone();
+            swapped[n] = match info.operands[n] {
+                OperandKind::Src => operand(&OperandKind::Dst, n),
+                OperandKind::Dst => operand(&OperandKind::Src, n),
+            };
+            assert!(Instruction::from_operands(info.opcode, &swapped)
+              .is_err(), "{} operand {}", info.mnemonic, n);
+        }
+    }
+}
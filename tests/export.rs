@@ -0,0 +1,39 @@
+use synacor_vm::{
+    asm::{DisAsmOpts, ImageMap},
+    assembler::assemble,
+};
+
+fn image_map(source: &str) -> (ImageMap, DisAsmOpts) {
+    let image = assemble(source).unwrap();
+    let opts = DisAsmOpts::default();
+    (ImageMap::new(&image, &opts), opts)
+}
+
+#[test]
+fn json_statements() {
+    let (map, opts) = image_map("call 4\nhalt\nnoop\nout 'x'\nret\n");
+    let json = map.to_json(&opts).unwrap();
+
+    let stmts = json["statements"].as_array().unwrap();
+    assert_eq!(stmts[0]["mnemonic"], "call");
+    assert_eq!(stmts[0]["target"], 4);
+    assert_eq!(stmts[0]["operands"][0]["symbol"], "fn0");
+    assert_eq!(stmts[0]["text"], "call fn0");
+
+    let callee = stmts.iter().find(|s| s["addr"] == 4).unwrap();
+    assert_eq!(callee["label"], "fn0");
+    assert_eq!(callee["xrefs"][0]["from"], 0);
+    assert_eq!(callee["xrefs"][0]["kind"], "call");
+}
+
+#[test]
+fn html_links_targets() {
+    let (map, opts) = image_map("jmp 3\nhalt\nhalt\n");
+    let mut html = Vec::new();
+    map.write_html(&mut html, "test", &opts).unwrap();
+    let html = String::from_utf8(html).unwrap();
+
+    assert!(html.contains("<title>test</title>"));
+    assert!(html.contains("id=\"a3\""));
+    assert!(html.contains("<a class=\"sym\" href=\"#a3\">lbl0</a>"));
+}
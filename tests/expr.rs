@@ -0,0 +1,54 @@
+use synacor_vm::{
+    asm::Labels,
+    expr::{Expr, ExprError},
+    vm::Vm,
+};
+
+fn eval(source: &str, vm: &Vm) -> Result<i64, ExprError> {
+    let mut labels = Labels::new();
+    labels.insert(100, "buf".to_string());
+    Expr::parse(source, &labels)?.eval(vm)
+}
+
+#[test]
+fn evaluate() {
+    let mut vm = Vm::new();
+    vm.registers_mut()[0] = 4;
+    vm.registers_mut()[7] = 32767;
+    vm.memory_mut()[100] = 'a' as u16;
+    vm.memory_mut()[103] = 9;
+    vm.jump_to(20);
+    vm.push_stack(5);
+
+    assert_eq!(eval("r0 == 4 && R7 == 0x7fff", &vm), Ok(1));
+    assert_eq!(eval("reg0 + 1 * 2", &vm), Ok(6));
+    assert_eq!(eval("(r0 + 1) * 2", &vm), Ok(10));
+    assert_eq!(eval("[100] == 'a'", &vm), Ok(1));
+    assert_eq!(eval("mem[buf + 3]", &vm), Ok(9));
+    assert_eq!(eval("ip", &vm), Ok(20));
+    assert_eq!(eval("sp == 1 && top == 5", &vm), Ok(1));
+    assert_eq!(eval("1 | 2 ^ 3 & 0b110", &vm), Ok(1));
+    assert_eq!(eval("1 << 4 >> 2", &vm), Ok(4));
+    assert_eq!(eval("-r0 < 0 && !0 && ~0 == -1", &vm), Ok(1));
+    assert_eq!(eval("0o17 % 4", &vm), Ok(3));
+}
+
+#[test]
+fn errors() {
+    let mut vm = Vm::new();
+    assert_eq!(eval("nope == 1", &vm), Err(ExprError::UnknownLabel(
+      "nope".to_string())));
+    assert!(matches!(eval("r0 ==", &vm), Err(ExprError::SyntaxError(_))));
+    assert!(matches!(eval("(r0", &vm), Err(ExprError::SyntaxError(_))));
+    assert!(matches!(eval("r0 r1", &vm), Err(ExprError::SyntaxError(_))));
+    assert_eq!(eval("top", &vm), Err(ExprError::EmptyStack));
+    assert_eq!(eval("[32768]", &vm), Err(ExprError::BadAddress(32768)));
+    assert_eq!(eval("[-1]", &vm), Err(ExprError::BadAddress(-1)));
+    assert_eq!(eval("1 / r0", &vm), Err(ExprError::DivideByZero));
+
+    // the right-hand side isn't evaluated when it can't matter
+    assert_eq!(eval("sp > 0 && top == 5", &vm), Ok(0));
+    assert_eq!(eval("1 || 1 / 0", &vm), Ok(1));
+    vm.push_stack(5);
+    assert_eq!(eval("sp > 0 && top == 5", &vm), Ok(1));
+}
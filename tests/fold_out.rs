@@ -0,0 +1,19 @@
+use synacor_vm::{
+    asm::{DisAsmOpts, ImageMap},
+    assembler::assemble,
+};
+
+#[test]
+fn folds_out_runs_and_reassembles() {
+    let image = assemble("out 'o'\nout 'k'\nloop: out '!'\nout 10\njmp loop\n")
+      .unwrap();
+    assert_eq!(assemble(".out \"ok!\\n\"\njmp 4\n").unwrap(), image);
+
+    let opts = DisAsmOpts { fold_out: true, ..DisAsmOpts::default() };
+    let mut text = Vec::new();
+    ImageMap::new(&image, &opts).disasm(&mut text, &opts).unwrap();
+    let text = String::from_utf8(text).unwrap();
+
+    assert_eq!(text, ".out \"ok\"\nlbl0: .out \"!\\n\"\njmp lbl0\n");
+    assert_eq!(assemble(&text).unwrap(), image);
+}
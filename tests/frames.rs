@@ -0,0 +1,98 @@
+use synacor_vm::{
+    assembler::assemble,
+    vm::{CallMismatch, Frame, Vm},
+};
+
+fn vm_for(source: &str) -> Vm {
+    let mut vm = Vm::new();
+    vm.load(&assemble(source).unwrap()).unwrap();
+    vm
+}
+
+fn step(vm: &mut Vm) -> Option<CallMismatch> {
+    vm.step(&mut &b""[..], &mut Vec::new()).unwrap();
+    vm.call_mismatch()
+}
+
+#[test]
+fn nested_calls() {
+    let mut vm = vm_for("
+        call outer
+        halt
+        outer: push r0
+        call inner
+        pop r0
+        ret
+        inner: ret
+    ");
+
+    step(&mut vm);
+    step(&mut vm);
+    step(&mut vm);
+    assert_eq!(vm.frames(), &[
+        Frame { call_site: 0, target: 3, return_addr: 2, stack_depth: 0 },
+        Frame { call_site: 5, target: 10, return_addr: 7, stack_depth: 2 },
+    ]);
+
+    for _ in 0..3 {
+        assert_eq!(step(&mut vm), None);
+    }
+    assert_eq!(vm.ip(), 2);
+    assert!(vm.frames().is_empty());
+}
+
+#[test]
+fn mismatches() {
+    let mut vm = vm_for("
+        push 3
+        ret
+        call swap
+        call bad
+        halt
+        swap: pop r1
+        push r0
+        push r1
+        ret
+        bad: pop r1
+        push 0
+        ret
+    ");
+
+    step(&mut vm);
+    assert_eq!(step(&mut vm),
+      Some(CallMismatch::Unmatched { ret_site: 2, to: 3 }));
+
+    step(&mut vm);
+    for _ in 0..3 {
+        step(&mut vm);
+    }
+    assert_eq!(step(&mut vm),
+      Some(CallMismatch::StackDepth { ret_site: 14, depth: 1, expected: 0 }));
+    assert!(vm.frames().is_empty());
+
+    for _ in 0..3 {
+        step(&mut vm);
+    }
+    assert_eq!(step(&mut vm),
+      Some(CallMismatch::WrongAddress { ret_site: 19, to: 0, expected: 7 }));
+    assert!(vm.frames().is_empty());
+}
+
+#[test]
+fn abandoned_calls_are_dropped() {
+    let mut vm = vm_for("
+        loop: call here
+        here: pop r0
+        jmp loop
+    ");
+
+    for _ in 0..30 {
+        step(&mut vm);
+    }
+    assert_eq!(vm.frames(), &[
+        Frame { call_site: 0, target: 2, return_addr: 2, stack_depth: 0 },
+    ]);
+
+    vm.load(&assemble("halt").unwrap()).unwrap();
+    assert!(vm.frames().is_empty());
+}
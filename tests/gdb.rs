@@ -0,0 +1,249 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use synacor_vm::{
+    assembler::assemble,
+    gdb::{GdbServer, TARGET_XML},
+    vm::Vm,
+};
+
+struct Client {
+    conn: TcpStream,
+    ack: bool,
+}
+
+impl Client {
+    fn next(&mut self) -> u8 {
+        let mut byte = [0u8];
+        self.conn.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn send(&mut self, packet: &str) {
+        let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        let frame = format!("${}#{:02x}", packet, checksum);
+        self.conn.write_all(frame.as_bytes()).unwrap();
+        if self.ack {
+            assert_eq!(self.next(), b'+');
+        }
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.reply()
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.next(), b'$');
+        let mut reply = Vec::new();
+        loop {
+            match self.next() {
+                b'#' => break,
+                b'}' => {
+                    let b = self.next();
+                    reply.push(b ^ 0x20);
+                },
+                b => reply.push(b),
+            };
+        }
+        let checksum = [self.next(), self.next()];
+        let expected = reply.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(),
+          format!("{:02x}", expected));
+        if self.ack {
+            self.conn.write_all(b"+").unwrap();
+        }
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+// registers come back as little-endian hex words, pc last
+fn registers(reply: &str) -> Vec<u16> {
+    (0..reply.len()).step_by(4)
+      .map(|i| {
+          let lo = u8::from_str_radix(&reply[i..i + 2], 16).unwrap();
+          let hi = u8::from_str_radix(&reply[i + 2..i + 4], 16).unwrap();
+          u16::from_le_bytes([lo, hi])
+      })
+      .collect()
+}
+
+// a server for `source` on its own thread, and a client without acks
+fn connect<F>(source: &str, serve: F) -> (Client, thread::JoinHandle<Vec<u8>>)
+  where F: FnOnce(&mut GdbServer, &mut TcpStream, &mut Vec<u8>)
+    + Send + 'static {
+    let (mut client, server) = listen(source, serve);
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.ack = false;
+    (client, server)
+}
+
+// the same, with a client that still acks
+fn listen<F>(source: &str, serve: F) -> (Client, thread::JoinHandle<Vec<u8>>)
+  where F: FnOnce(&mut GdbServer, &mut TcpStream, &mut Vec<u8>)
+    + Send + 'static {
+    let mut vm = Vm::new();
+    vm.load(&assemble(source).unwrap()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_nodelay(true).unwrap();
+        let mut output = Vec::new();
+        serve(&mut GdbServer::new(vm), &mut conn, &mut output);
+        output
+    });
+
+    let conn = TcpStream::connect(addr).unwrap();
+    conn.set_nodelay(true).unwrap();
+    // a reply that never comes is a failure, not a hang
+    conn.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    (Client { conn, ack: true }, server)
+}
+
+#[test]
+fn scripted_session() {
+    let prog = assemble("
+        set r0, 5
+        loop: add r0, r0, 1
+        wmem count, r0
+        eq r1, r0, 8
+        jf r1, loop
+        out 'x'
+        halt
+        count: .word 0
+    ").unwrap();
+    let mut vm = Vm::new();
+    vm.load(&prog).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_nodelay(true).unwrap();
+        let mut output = Vec::new();
+        let mut server = GdbServer::new(vm);
+        server.serve(&mut conn, &mut io::empty(), &mut output).unwrap();
+        output
+    });
+
+    let conn = TcpStream::connect(addr).unwrap();
+    conn.set_nodelay(true).unwrap();
+    let mut client = Client { conn, ack: true };
+    assert!(client.request("qSupported:multiprocess+")
+      .contains("qXfer:features:read+"));
+
+    let mut xml = String::new();
+    loop {
+        let reply = client.request(
+          &format!("qXfer:features:read:target.xml:{:x},40", xml.len()));
+        xml.push_str(&reply[1..]);
+        if reply.starts_with('l') {
+            break;
+        }
+    }
+    assert_eq!(xml, TARGET_XML);
+
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.ack = false;
+
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(registers(&client.request("g")), vec![0; 9]);
+
+    // byte addresses are twice word addresses
+    assert_eq!(client.request("m0,6"), "010000800500");
+    assert_eq!(client.request("Z0,1c,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(registers(&client.request("g")),
+      vec![6, 0, 0, 0, 0, 0, 0, 0, 28]);
+    assert_eq!(client.request("m28,2"), "0600");
+
+    assert_eq!(client.request("P0=0600"), "OK");
+    assert_eq!(client.request("p0"), "0600");
+    assert_eq!(client.request("M28,2:0500"), "OK");
+    assert_eq!(client.request("m28,2"), "0500");
+    assert_eq!(client.request("M28,2:0600"), "OK");
+
+    // back round the loop
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p8"), "0600");
+
+    assert_eq!(client.request("z0,1c,2"), "OK");
+    assert_eq!(client.request("Z2,28,2"), "OK");
+    assert_eq!(client.request("c"), "T05watch:28;");
+    assert_eq!(client.request("m28,2"), "0700");
+    assert_eq!(client.request("z2,28,2"), "OK");
+
+    assert_eq!(client.request("c"), "W00");
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+    assert_eq!(client.request("m10000,2"), "E01");
+
+    client.conn.write_all(b"$k#6b").unwrap();
+    assert_eq!(server.join().unwrap(), b"x");
+}
+
+#[test]
+fn interrupts_keep_what_came_before() {
+    let (mut client, server) = connect("loop: jmp loop\n",
+      |server, conn, output| {
+          server.serve(conn, &mut io::empty(), output).unwrap();
+      });
+
+    // a packet sent while running is answered once we've stopped
+    client.send("c");
+    client.send("?");
+    client.conn.write_all(&[0x03]).unwrap();
+    assert_eq!(client.reply(), "S02");
+    assert_eq!(client.reply(), "S05");
+
+    client.conn.write_all(b"$k#6b").unwrap();
+    server.join().unwrap();
+}
+
+#[test]
+fn console_output() {
+    let (mut client, server) = connect("
+        out 'h'
+        out 'i'
+        out 10
+        out '!'
+        halt
+    ", |server, conn, _| {
+        server.serve_console(conn, &mut io::empty()).unwrap();
+    });
+
+    client.send("c");
+    assert_eq!(client.reply(), "O68690a");
+    assert_eq!(client.reply(), "O21");
+    assert_eq!(client.reply(), "W00");
+
+    client.conn.write_all(b"$k#6b").unwrap();
+    assert_eq!(server.join().unwrap(), b"");
+}
+
+#[test]
+fn interrupts_while_awaiting_an_ack() {
+    let (mut client, server) = listen("loop: out 'h'\nout 10\njmp loop\n",
+      |server, conn, _| {
+          server.serve_console(conn, &mut io::empty()).unwrap();
+      });
+
+    // the interrupt goes out just ahead of the ack for some output
+    client.send("c");
+    client.ack = false;
+    assert_eq!(client.reply(), "O680a");
+    client.conn.write_all(&[0x03, b'+']).unwrap();
+    client.ack = true;
+
+    let stop = (0..1000).map(|_| client.reply())
+      .find(|reply| !reply.starts_with('O'));
+    assert_eq!(stop.as_deref(), Some("S02"));
+
+    client.conn.write_all(b"$k#6b").unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,64 @@
+use synacor_vm::{
+    asm::StringHint,
+    map::{ProjectMap, Region, RegionKind},
+};
+
+#[test]
+fn reads_legacy_label_files() {
+    let text = "10\tmain\n20\t.str\n30\t.ascii\t4\n40\t.nostr\n";
+    let map = ProjectMap::read(&mut text.as_bytes()).unwrap();
+
+    assert_eq!(map.labels.get(&10).map(String::as_str), Some("main"));
+    assert_eq!(map.string_hints.get(&20), Some(&StringHint::Prefixed));
+    assert_eq!(map.string_hints.get(&30), Some(&StringHint::Raw(4)));
+    assert_eq!(map.string_hints.get(&40), Some(&StringHint::Never));
+}
+
+#[test]
+fn write_then_read_is_identity() {
+    let mut map = ProjectMap::new();
+    map.labels.insert(0, "start".to_string());
+    map.entries.insert(0);
+    map.signatures.insert(100, "print(r0: str)".to_string());
+    map.comments.insert(100, vec!["prints\tthings".to_string()]);
+    map.string_hints.insert(200, StringHint::Raw(3));
+    map.regions.push(Region { start: 300, len: 4,
+      kind: RegionKind::Struct("pair".to_string()) });
+    map.regions.push(Region { start: 400, len: 2, kind: RegionKind::Data });
+
+    let mut text = Vec::new();
+    map.write(&mut text).unwrap();
+    let read = ProjectMap::read(&mut text.as_slice()).unwrap();
+    assert_eq!(read, map);
+}
+
+#[test]
+fn rejects_unknown_directives() {
+    assert!(ProjectMap::read(&mut "5\t.bogus\n".as_bytes()).is_err());
+    assert!(ProjectMap::read(&mut "5\t.region\t2\tblob\n".as_bytes()).is_err());
+}
+
+#[test]
+fn three_way_merge() {
+    let mut base = ProjectMap::new();
+    base.labels.insert(1, "one".to_string());
+    base.labels.insert(2, "two".to_string());
+    base.labels.insert(3, "three".to_string());
+
+    let mut ours = base.clone();
+    ours.labels.insert(1, "uno".to_string());
+    ours.labels.insert(3, "ours".to_string());
+
+    let mut theirs = base.clone();
+    theirs.labels.remove(&2);
+    theirs.labels.insert(3, "theirs".to_string());
+    theirs.entries.insert(1);
+
+    let (merged, conflicts) = ProjectMap::merge(&base, &ours, &theirs);
+    assert_eq!(merged.labels.get(&1).map(String::as_str), Some("uno"));
+    assert_eq!(merged.labels.get(&2), None);
+    assert_eq!(merged.labels.get(&3).map(String::as_str), Some("ours"));
+    assert!(merged.entries.contains(&1));
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].addr, 3);
+}
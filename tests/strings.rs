@@ -0,0 +1,74 @@
+use synacor_vm::{
+    asm::{string_confidence, DisAsmOpts, ImageMap, StringHint, StringKind},
+    map::ProjectMap,
+};
+
+fn words(text: &str) -> Vec<u16> {
+    text.bytes().map(u16::from).collect()
+}
+
+fn disasm(image: &[u16], opts: &DisAsmOpts) -> String {
+    let mut text = Vec::new();
+    ImageMap::new(image, opts).disasm(&mut text, opts).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+fn with_map(map: &str) -> DisAsmOpts {
+    let map = ProjectMap::read(&mut map.as_bytes()).unwrap();
+    DisAsmOpts {
+        autolabel: false,
+        string_hints: map.string_hints,
+        ..DisAsmOpts::default()
+    }
+}
+
+#[test]
+fn detects_strings_over_the_threshold() {
+    let mut image = vec![11];
+    image.extend(words("hello world"));
+    image.push(0);
+
+    let opts = DisAsmOpts { strings: true, ..DisAsmOpts::default() };
+    assert_eq!(disasm(&image, &opts), ".str \"hello world\"\nhalt\n");
+
+    // detection is opt-in, and gated on confidence
+    let off = disasm(&image, &DisAsmOpts::default());
+    assert!(!off.contains(".str"));
+    let strict = DisAsmOpts { string_threshold: 0.95, ..opts };
+    assert!(!disasm(&image, &strict).contains(".str"));
+
+    // short runs and punctuation are less convincing
+    let word = string_confidence(&words("hello world"), StringKind::Raw);
+    assert!(string_confidence(&words("hi"), StringKind::Raw) < word);
+    assert!(string_confidence(&words("#$%&"), StringKind::Raw) < 0.5);
+    assert!(string_confidence(&words("hi"), StringKind::Prefixed)
+      > string_confidence(&words("hi"), StringKind::Raw));
+}
+
+#[test]
+fn hints_override_detection() {
+    let mut image = vec![2];
+    image.extend(words("ok"));
+    image.extend(words("abc"));
+    image.push(0);
+
+    assert_eq!(disasm(&image, &with_map("0\t.str\n3\t.ascii\t3\n")),
+      ".str \"ok\"\n.ascii \"abc\"\nhalt\n");
+
+    let opts = DisAsmOpts {
+        strings: true,
+        ..with_map("0\t.nostr\n")
+    };
+    assert!(disasm(&image, &opts).starts_with("push 'o'\n"));
+}
+
+#[test]
+fn empty_raw_strings_are_decoded() {
+    assert!(ProjectMap::read(&mut "0\t.ascii\t0\n".as_bytes()).is_err());
+
+    // a hint built by hand can't stall the map either
+    let image = [21, 21, 0];
+    let mut opts = DisAsmOpts { autolabel: false, ..DisAsmOpts::default() };
+    opts.string_hints.insert(0, StringHint::Raw(0));
+    assert_eq!(disasm(&image, &opts), "noop\nnoop\nhalt\n");
+}
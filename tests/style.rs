@@ -0,0 +1,35 @@
+use synacor_vm::{
+    asm::{DisAsmOpts, DisAsmStyle, ImageMap},
+    assembler::assemble,
+};
+
+const SOURCE: &str = "start: out 'a'\nadd r1, r2, 65\njmp start\n";
+
+fn render(style: &str) -> (String, Vec<u16>) {
+    let image = assemble(SOURCE).unwrap();
+    let opts = DisAsmOpts {
+        style: style.parse().unwrap(),
+        ..DisAsmOpts::default()
+    };
+    let mut text = Vec::new();
+    ImageMap::new(&image, &opts).disasm(&mut text, &opts).unwrap();
+    (String::from_utf8(text).unwrap(), image)
+}
+
+#[test]
+fn styled_output_reassembles() {
+    let (text, image) = render("radix=hex,chars=out,regs=reg,upper,align");
+    assert_eq!(text,
+      "lbl0: OUT   'a'\n      ADD   reg1, reg2, 0x41\n      JMP   lbl0\n");
+    assert_eq!(assemble(&text).unwrap(), image);
+
+    let (text, image) = render("addrs,chars=never,regs=R");
+    assert_eq!(text, "lbl0: out 97\nadd R1, R2, 65\njmp 0\n");
+    assert_eq!(assemble(&text).unwrap(), image);
+}
+
+#[test]
+fn rejects_unknown_options() {
+    assert!("radix=bin".parse::<DisAsmStyle>().is_err());
+    assert_eq!("".parse::<DisAsmStyle>(), Ok(DisAsmStyle::default()));
+}
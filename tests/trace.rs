@@ -0,0 +1,323 @@
+use std::{
+    env,
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+use synacor_vm::assembler::assemble;
+
+// a scratch directory for one test's files, removed afterwards
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(name: &str) -> Self {
+        let dir = env::temp_dir()
+          .join(format!("syntrace-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        Scratch(dir)
+    }
+
+    fn file(&self, name: &str, contents: &[u8]) -> String {
+        let path = self.0.join(name);
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn program(&self, source: &str) -> String {
+        let image: Vec<u8> = assemble(source).unwrap().iter()
+          .flat_map(|word| word.to_le_bytes())
+          .collect();
+        self.file("prog.bin", &image)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn syntrace(scratch: &Scratch, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_syntrace"))
+      .args(args)
+      .arg("--history").arg(scratch.0.join("history"))
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// stdout without its colours
+fn text(out: &Output) -> String {
+    plain(&out.stdout)
+}
+
+// terminal output without escape sequences or carriage returns
+fn plain(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    let mut text = String::new();
+    let mut chars = output.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                chars.next();
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            },
+            '\r' => { },
+            c => text.push(c),
+        };
+    }
+    text
+}
+
+#[test]
+fn batch_always_exits() {
+    let scratch = Scratch::new("batch");
+    let prog = scratch.program("noop\nhalt\n");
+    let script = scratch.file("script", b"label 0 start\n");
+
+    // nowhere to save the label: a warning, not an error or a prompt
+    let out = syntrace(&scratch, &["--batch", "-s", &script, &prog], "help\n");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(stdout.contains("labels not saved"), "{}", stdout);
+    assert!(!stdout.contains("(q)uit"), "{}", stdout);
+
+    // with a map, it's saved on the way out
+    let map = scratch.file("prog.map", b"");
+    let out = syntrace(&scratch,
+      &["--batch", "-m", &map, "-s", &script, &prog], "help\n");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(out.status.success());
+    assert!(!stdout.contains("(q)uit"), "{}", stdout);
+    assert_eq!(fs::read_to_string(&map).unwrap(), "0\tstart\n");
+}
+
+#[test]
+fn sessions_round_trip() {
+    let scratch = Scratch::new("session");
+    let prog = scratch.program("
+        in r0
+        out r0
+        in r1
+        out r1
+        out 10
+        halt
+    ");
+    // neither the input nor the output is valid UTF-8
+    let input = scratch.file("input", b"\xff\x80");
+    let session = scratch.0.join("session.json");
+    let session = session.to_str().unwrap();
+
+    let save = scratch.file("save",
+      format!("continue 4\nsession save {}\n", session).as_bytes());
+    let out = syntrace(&scratch, &["--batch", "-i", &input, "-s", &save, &prog],
+      "");
+    assert!(out.status.success());
+
+    let load = scratch.file("load",
+      format!("session load {}\ncontinue\n", session).as_bytes());
+    let out = syntrace(&scratch, &["--batch", "-s", &load, &prog], "");
+    assert!(out.stdout.windows(3).any(|w| w == b"\xff\x80\n"),
+      "{}", String::from_utf8_lossy(&out.stdout));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("HALT"));
+
+    // a session that would put us outside memory isn't loaded
+    let text = fs::read_to_string(session).unwrap();
+    fs::write(session, text.replace("\"ip\":4", "\"ip\":40000")).unwrap();
+    let out = syntrace(&scratch, &["--batch", "-s", &load, &prog], "");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("\"ip\" is outside memory"), "{}", stdout);
+    assert!(!stdout.contains("HALT"), "{}", stdout);
+
+    // nor one that's read past the end of its input
+    fs::write(session, text.replace("\"input_position\":1",
+      "\"input_position\":3")).unwrap();
+    let out = syntrace(&scratch, &["--batch", "-s", &load, &prog], "");
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("\"input_position\" is past the input"),
+      "{}", stdout);
+    assert!(!stdout.contains("HALT"), "{}", stdout);
+}
+
+#[test]
+fn xrefs() {
+    let scratch = Scratch::new("xrefs");
+    let prog = scratch.program("call print\nhalt\nprint: ret\n");
+    let script = scratch.file("script", b"xrefs 3\nxrefs 0\n");
+    let out = syntrace(&scratch, &["-a", "--batch", "-s", &script, &prog], "");
+    let text = text(&out);
+    assert!(text.contains("call from 0\n"), "{}", text);
+    assert!(text.contains("no xrefs to 0\n"), "{}", text);
+}
+
+// counts r0 down, then calls a routine that uses the stack itself
+const CALLS: &str = "
+    set r0, 3
+    loop: add r0, r0, 32767
+    jt r0, loop
+    call f
+    out 'y'
+    halt
+    f: push 5
+    pop r1
+    push 7
+    out 'f'
+    pop r2
+    ret
+    msg: .str \"hi\"
+";
+
+fn run_script(name: &str, script: &str) -> String {
+    let scratch = Scratch::new(name);
+    let prog = scratch.program(CALLS);
+    let map = scratch.file("prog.map", b"15\tf\n26\tmsg\n");
+    let script = scratch.file("script", script.as_bytes());
+    let out = syntrace(&scratch, &["--batch", "-m", &map, "-s", &script, &prog],
+      "");
+    assert!(out.status.success());
+    text(&out)
+}
+
+#[test]
+fn stepping_over_calls() {
+    // out of the loop, then over the call
+    let text = run_script("next",
+      "continue 7\nuntil\nstatus\nnext\nstatus\n");
+    let ips: Vec<_> = text.lines()
+      .filter(|l| l.starts_with("ip "))
+      .map(|l| l.split(" / ").next().unwrap())
+      .collect();
+    assert_eq!(ips, ["ip 10", "ip 12"], "{}", text);
+
+    // out of the call, though it pushed and popped on the way
+    let text = run_script("finish", "continue 19\nfinish\nstatus\n");
+    assert!(text.contains("ip 12 / regs [0, 5, 7, 0, 0, 0, 0, 0] / stack# 0"),
+      "{}", text);
+    let text = run_script("not-in-call", "finish\n");
+    assert!(text.contains("not in a call"), "{}", text);
+}
+
+#[test]
+fn examining_memory_and_the_stack() {
+    let text = run_script("examine",
+      "x msg 3\nx msg 3 d\nx msg 1 s\ncontinue 21\nstack\n");
+    let lines: Vec<_> = text.lines()
+      .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+      .collect();
+    for line in [
+        "msg (26) 0002 0068 0069",
+        "msg (26) 2 104 105",
+        "msg (26) \"hi\"",
+        "1 7",
+        "0 12 ; ret to 12 (frame #1)",
+    ] {
+        assert!(lines.iter().any(|l| l == line), "{}\n{}", line, text);
+    }
+}
+
+#[test]
+fn disassembling_live_memory() {
+    let text = run_script("dis",
+      "continue 10\nnext\nbreak 14\npoke 12 21\ndis 10 4\n");
+    let listing: Vec<_> = text.lines()
+      .skip_while(|l| !l.contains("call 15"))
+      .take(4)
+      .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+      .collect();
+    assert_eq!(listing, ["10 call 15", "=> 12 noop", "13 'y'", "* 14 halt"],
+      "{}", text);
+}
+
+// syntrace on a terminal, by way of script(1), typing each line once it's
+//   been prompted for; `None` if there's no way to run it like that
+#[cfg(target_os = "linux")]
+fn on_terminal(scratch: &Scratch, args: &[&str], lines: &[&str]
+  ) -> Option<String> {
+    use std::io::Read;
+
+    let quote = |arg: &str| format!("'{}'", arg.replace('\'', "'\\''"));
+    let history = scratch.0.join("history");
+    let mut command = vec![
+        quote(env!("CARGO_BIN_EXE_syntrace")),
+        "--history".to_string(),
+        quote(history.to_str().unwrap()),
+    ];
+    command.extend(args.iter().map(|arg| quote(arg)));
+
+    // a prompt that never comes is a failure, not a hang
+    let mut child = Command::new("timeout")
+      .args(["30", "script", "-qec", &command.join(" "), "/dev/null"])
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .spawn()
+      .ok()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+
+    // the editor drops anything typed before it's ready, so wait for the
+    //   prompt; completion redraws it too, but only after a newline is
+    //   it for the next line
+    let mut seen = Vec::new();
+    let mut from = None;
+    let mut buf = [0u8; 1024];
+    for line in lines {
+        loop {
+            let start = match from {
+                None => Some(0),
+                Some(from) => seen[from..].iter().position(|&b| b == b'\n')
+                  .map(|n| from + n),
+            };
+            let prompted = start.is_some_and(|start| seen[start..]
+              .windows(9).any(|w| w == b"ictrace> "));
+            if prompted {
+                break;
+            }
+            match stdout.read(&mut buf).unwrap() {
+                0 => return Some(plain(&seen)),
+                n => seen.extend(&buf[..n]),
+            };
+        }
+        from = Some(seen.len());
+        stdin.write_all(line.as_bytes()).unwrap();
+    }
+
+    stdout.read_to_end(&mut seen).unwrap();
+    child.wait().unwrap();
+    Some(plain(&seen))
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn line_editing() {
+    let scratch = Scratch::new("editing");
+    let prog = scratch.program(CALLS);
+    let map = scratch.file("prog.map", b"15\tfunc\n");
+    let args = ["-m", &map, &prog];
+
+    // labels and commands complete
+    let text = match on_terminal(&scratch, &args,
+      &["break fu\t\r", "xre\t 15\r", "quit\r"]) {
+        Some(text) => text,
+        None => return,
+    };
+    assert!(text.contains("breakpoint 1 at func (15)\n"), "{}", text);
+    assert!(text.contains("call from 10\n"), "{}", text);
+
+    // and are remembered as they were run, for next time
+    let history = fs::read_to_string(scratch.0.join("history")).unwrap();
+    assert_eq!(history.lines().skip(1).collect::<Vec<_>>(),
+      ["break func", "xrefs 15", "quit"]);
+    let text = on_terminal(&scratch, &args, &["\x1b[A\x1b[A\r", "quit\r"])
+      .unwrap();
+    assert!(text.contains("call from 10\n"), "{}", text);
+}
@@ -0,0 +1,80 @@
+use synacor_vm::{
+    assembler::assemble,
+    unpack::{
+        diff_regions,
+        run_until,
+        StopEvent,
+        StopReason,
+        Unpacked,
+        DEFAULT_STEP_LIMIT,
+    },
+    vm::Vm,
+};
+
+fn run(source: &str, events: &[StopEvent], input: &[u8]) -> Unpacked {
+    let mut vm = Vm::new();
+    vm.load(&assemble(source).unwrap()).unwrap();
+    run_until(vm, events, input).unwrap()
+}
+
+// writes over its own code, then reads a byte
+const PROGRAM: &str = "
+    wmem patch, 21
+    wmem patch + 1, 21
+    out 'k'
+    in r0
+    patch: halt
+    halt
+";
+
+#[test]
+fn stops_at_events() {
+    let input = run(PROGRAM, &[StopEvent::Input], b"");
+    assert_eq!(input.reason, StopReason::Event(StopEvent::Input));
+    assert_eq!((input.steps, input.vm.ip(), &input.output[..]),
+      (3, 8, &b"k"[..]));
+
+    let addr = run(PROGRAM, &[StopEvent::Address(6)], b"x");
+    assert_eq!(addr.reason, StopReason::Event(StopEvent::Address(6)));
+    assert_eq!(addr.steps, 2);
+
+    let steps = run(PROGRAM, &[StopEvent::Steps(1)], b"x");
+    assert_eq!(steps.reason, StopReason::Event(StopEvent::Steps(1)));
+    assert_eq!(steps.vm.ip(), 3);
+
+    // the patched halts are noops now, and the run falls off the end
+    let halted = run(PROGRAM, &[StopEvent::Input], b"x");
+    assert_eq!(halted.reason, StopReason::Halted);
+    assert_eq!(halted.vm.registers()[0], 'x' as u16);
+}
+
+#[test]
+fn running_out_of_input_stops() {
+    let out = run(PROGRAM, &[StopEvent::Address(100)], b"");
+    assert_eq!(out.reason, StopReason::InputExhausted);
+    assert_eq!(out.vm.ip(), 8);
+}
+
+#[test]
+fn runs_are_capped() {
+    let out = run("loop: jmp loop\n", &[StopEvent::Address(100)], b"");
+    assert_eq!(out.reason,
+      StopReason::Event(StopEvent::Steps(DEFAULT_STEP_LIMIT)));
+    assert_eq!(out.steps, DEFAULT_STEP_LIMIT);
+}
+
+#[test]
+fn diffs_changed_regions() {
+    let original = [1, 2, 3, 4, 5, 6];
+    assert_eq!(diff_regions(&original, &original), vec![]);
+    assert_eq!(diff_regions(&original, &[1, 0, 0, 4, 5, 0]),
+      vec![1..3, 5..6]);
+    // past the end of either image counts as zeros
+    assert_eq!(diff_regions(&original, &[1, 2, 3, 4, 5, 6, 0, 7, 7]),
+      vec![7..9]);
+    assert_eq!(diff_regions(&[0, 9], &[9]), vec![0..2]);
+
+    let unpacked = run(PROGRAM, &[StopEvent::Input], b"x");
+    let image = assemble(PROGRAM).unwrap();
+    assert_eq!(diff_regions(&image, unpacked.vm.memory()), vec![10..12]);
+}